@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+
+use chess::Board;
+use serde::{Serialize, Deserialize};
+
+/// A hash-keyed cache of static evaluations, so that positions reached again in sibling
+/// subtrees or later searches are not evaluated twice. Each slot holds one entry, which
+/// is replaced by any newer evaluation with the same index. Only the size is saved with
+/// the engine; the entries are allocated on first use.
+#[derive(Serialize, Deserialize)]
+pub struct EvalCache {
+    size: usize, // in entries, zero to disable the cache
+    #[serde(skip)]
+    entries: Vec<Option<(u64, f64)>>,
+    #[serde(skip)]
+    hits: u64,
+    #[serde(skip)]
+    misses: u64,
+}
+
+impl EvalCache {
+    /// Create an empty cache with room for the given number of entries.
+    pub fn new(size: usize) -> EvalCache {
+        EvalCache {
+            size,
+            entries: Vec::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Return the number of entries the cache has room for.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Return the key of `board`, including the halfmove clock if the evaluation
+    /// depends on it.
+    pub fn key(board: &Board, halfmove_clock: Option<u16>) -> u64 {
+        match halfmove_clock {
+            Some(clock) => board.get_hash() ^ (clock as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+            None => board.get_hash(),
+        }
+    }
+
+    /// Return the cached evaluation with the given key, if there is one.
+    pub fn get(&mut self, key: u64) -> Option<f64> {
+        if self.size == 0 {
+            return None;
+        }
+
+        match self.entries.get(key as usize % self.size) {
+            Some(Some((stored_key, value))) if *stored_key == key => {
+                self.hits += 1;
+                Some(*value)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Store the evaluation with the given key.
+    pub fn insert(&mut self, key: u64, value: f64) {
+        if self.size == 0 {
+            return;
+        }
+        if self.entries.len() != self.size {
+            self.entries = vec![None; self.size];
+        }
+
+        self.entries[key as usize % self.size] = Some((key, value));
+    }
+
+    /// Forget every cached evaluation, for example because the evaluator has been
+    /// trained. The statistics are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Change the number of entries, forgetting every cached evaluation.
+    pub fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.entries.clear();
+    }
+
+    /// Reset the hit and miss counts.
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    /// Return the number of hits and misses since the statistics were last reset.
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    /// Return the proportion of lookups that were hits, or zero if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl Default for EvalCache {
+    fn default() -> EvalCache {
+        EvalCache::new(1 << 16)
+    }
+}
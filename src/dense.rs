@@ -0,0 +1,236 @@
+#![allow(dead_code)]
+
+use crate::eval::{Activation, Evaluator, Network, Perspective};
+use crate::features::FeatureSet;
+use chess::Board;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
+
+/// Return true if the activation function is applied to the outputs of the layer with
+/// index `layer` of a network of `num_layers` layers. As in neuroflow, the last layer is
+/// linear unless it is also the first.
+pub fn is_activated(layer: usize, num_layers: usize) -> bool {
+    layer == 0 || layer + 1 < num_layers
+}
+
+/// A network for inference that is saved to its own file in JSON format.
+pub trait NetworkFile: Serialize + DeserializeOwned {
+    /// Load a network saved by `save`.
+    fn load(filename: &str) -> Self {
+        let contents = std::fs::read_to_string(filename).unwrap();
+        serde_json::from_str(&contents).unwrap()
+    }
+
+    /// Save the network to the given file in JSON format.
+    fn save(&self, filename: &str) {
+        let serialized = serde_json::to_string(&self).unwrap();
+        std::fs::write(filename, serialized).unwrap();
+    }
+}
+
+/// Return the inputs of a network with the given features and perspective for a board
+/// in `f32`, and the sign that turns its output into a value for the side to move.
+pub fn board_inputs(feature_set: FeatureSet, perspective: Perspective, board: &Board, halfmove_clock: u16) -> (Vec<f32>, f32) {
+    let (features, sign) = perspective.inputs(feature_set, board, halfmove_clock);
+    (features.iter().map(|&x| x as f32).collect(), sign as f32)
+}
+
+/// A fully connected layer, with the weights stored row by row, one row per output.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DenseLayer {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub weights: Vec<f32>,
+    pub biases: Vec<f32>,
+}
+
+impl DenseLayer {
+    /// Return the weights of the given output neuron.
+    pub fn row(&self, output: usize) -> &[f32] {
+        &self.weights[output * self.inputs..(output + 1) * self.inputs]
+    }
+}
+
+/// A read-only copy of a trained network for fast inference in `f32`. Evaluation only
+/// needs `&self`, so one network can be shared between threads, and many positions can
+/// be evaluated in a single batch.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DenseNetwork {
+    feature_set: FeatureSet,
+    activation: Activation,
+    #[serde(default = "side_to_move")]
+    perspective: Perspective,
+    layers: Vec<DenseLayer>,
+}
+
+/// The perspective of dense networks saved before it was recorded, which could only be
+/// converted from networks evaluating for the side to move.
+pub fn side_to_move() -> Perspective {
+    Perspective::SideToMove
+}
+
+impl DenseNetwork {
+    /// Convert a trained network, keeping its perspective.
+    pub fn from_network(network: &Network) -> DenseNetwork {
+        let value = serde_json::to_value(network).unwrap();
+
+        // neuroflow stores the bias of each neuron as the first of its weights
+        let layers = value["eval_nn"]["layers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|layer| {
+                let rows: Vec<Vec<f32>> = serde_json::from_value(layer["w"].clone()).unwrap();
+                DenseLayer {
+                    inputs: rows[0].len() - 1,
+                    outputs: rows.len(),
+                    weights: rows.iter().flat_map(|row| row[1..].to_vec()).collect(),
+                    biases: rows.iter().map(|row| row[0]).collect(),
+                }
+            })
+            .collect();
+
+        DenseNetwork {
+            feature_set: network.feature_set(),
+            activation: network.spec().activation,
+            perspective: network.spec().perspective,
+            layers,
+        }
+    }
+
+    /// Return the set of features the network takes.
+    pub fn feature_set(&self) -> FeatureSet {
+        self.feature_set
+    }
+
+    /// Return the activation function of the hidden layers.
+    pub fn activation(&self) -> Activation {
+        self.activation
+    }
+
+    /// Return the point of view of the outputs of the network.
+    pub fn perspective(&self) -> Perspective {
+        self.perspective
+    }
+
+    /// Return the layers of the network, from the inputs to the output.
+    pub fn layers(&self) -> &[DenseLayer] {
+        &self.layers
+    }
+
+    /// Return true if the activation function is applied to the outputs of the layer
+    /// with the given index.
+    pub fn is_activated(&self, layer: usize) -> bool {
+        is_activated(layer, self.layers.len())
+    }
+
+    /// Return the output of the network for the given features.
+    pub fn calc(&self, features: &[f32]) -> f32 {
+        let mut values = features.to_vec();
+        for (i, layer) in self.layers.iter().enumerate() {
+            values = (0..layer.outputs)
+                .map(|output| {
+                    let sum = layer.biases[output]
+                        + layer.row(output).iter().zip(&values).map(|(w, x)| w * x).sum::<f32>();
+                    if self.is_activated(i) { self.activation.apply(sum) } else { sum }
+                })
+                .collect();
+        }
+
+        values[0]
+    }
+
+    /// Return the outputs of the network for a batch of feature vectors, each of the
+    /// network's input size, stored one after another. Each layer is applied to the
+    /// whole batch before moving on to the next, so its weights stay in cache.
+    pub fn calc_batch(&self, features: &[f32]) -> Vec<f32> {
+        let mut values = features.to_vec();
+        let mut width = self.layers[0].inputs;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let batch_size = values.len() / width;
+            let mut outputs = vec![0.0; batch_size * layer.outputs];
+            for output in 0..layer.outputs {
+                let row = layer.row(output);
+                for (position, inputs) in values.chunks_exact(width).enumerate() {
+                    let sum = layer.biases[output] + row.iter().zip(inputs).map(|(w, x)| w * x).sum::<f32>();
+                    outputs[position * layer.outputs + output] =
+                        if self.is_activated(i) { self.activation.apply(sum) } else { sum };
+                }
+            }
+            values = outputs;
+            width = layer.outputs;
+        }
+
+        values
+    }
+
+    /// Return the evaluation of `board`, positive when the side to move is better.
+    pub fn evaluate_board(&self, board: &Board, halfmove_clock: u16) -> f32 {
+        let (features, sign) = board_inputs(self.feature_set, self.perspective, board, halfmove_clock);
+        self.calc(&features) * sign
+    }
+
+    /// Return the evaluations of many boards, each given with its halfmove clock and
+    /// from the perspective of its side to move.
+    pub fn evaluate_boards(&self, boards: &[(Board, u16)]) -> Vec<f32> {
+        let (features, signs): (Vec<Vec<f32>>, Vec<f32>) = boards
+            .iter()
+            .map(|(board, halfmove_clock)| board_inputs(self.feature_set, self.perspective, board, *halfmove_clock))
+            .unzip();
+
+        self.calc_batch(&features.concat()).iter().zip(signs).map(|(value, sign)| value * sign).collect()
+    }
+}
+
+impl NetworkFile for DenseNetwork {}
+
+#[typetag::serde]
+impl Evaluator for DenseNetwork {
+    fn evaluate(&mut self, board: &Board, halfmove_clock: u16) -> f64 {
+        self.evaluate_board(board, halfmove_clock) as f64
+    }
+
+    fn uses_halfmove_clock(&self) -> bool {
+        self.feature_set.uses_halfmove_clock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::NetworkSpec;
+    use crate::features::tests::mirrored_pairs;
+
+    // legacy networks, which evaluate from White's point of view, must convert too
+    #[test]
+    fn dense_networks_match_their_networks() {
+        let boards: Vec<(Board, u16)> = mirrored_pairs()
+            .into_iter()
+            .flat_map(|(board, mirrored)| [board, mirrored])
+            .enumerate()
+            .map(|(i, board)| (board, 7 * i as u16))
+            .collect();
+
+        for feature_set in [FeatureSet::Compact, FeatureSet::OneHot, FeatureSet::Extended] {
+            for hidden_sizes in [&[][..], &[8], &[16, 4]] {
+                for (activation, perspective) in [Activation::Tanh, Activation::Sigmoid]
+                    .into_iter()
+                    .flat_map(|activation| [(activation, Perspective::SideToMove), (activation, Perspective::White)])
+                {
+                    let mut spec = NetworkSpec::new(feature_set).hidden_sizes(hidden_sizes).activation(activation);
+                    spec.perspective = perspective;
+                    let mut network = Network::new(spec.clone());
+                    let dense = DenseNetwork::from_network(&network);
+
+                    let batch = dense.evaluate_boards(&boards);
+                    for (&(board, halfmove_clock), batch_value) in boards.iter().zip(batch) {
+                        let value = network.evaluate(&board, halfmove_clock);
+                        for dense_value in [dense.evaluate_board(&board, halfmove_clock), batch_value] {
+                            assert!((value - dense_value as f64).abs() < 1e-4, "{} != {} for {} with {:?}", value, dense_value, board, spec);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
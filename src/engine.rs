@@ -1,13 +1,27 @@
 #![allow(dead_code)]
 
-use chess::{Board, BoardStatus, ChessMove, Color, File, Game, GameResult, MoveGen, Rank, Square};
-use neuroflow::FeedForward;
+use crate::cache::EvalCache;
+use crate::eval::{side_sign, Evaluator, Network, NetworkSpec};
+use crate::history::History;
+use crate::training::{
+    self, lambda_returns, Checkpoints, Exploration, LabelledPosition, LearningCurve, TrainingConfig, TrainingProgress, TrainingTarget,
+};
+use crate::samples::{Sample, SampleWriter};
+use crate::wdl::WdlModel;
+use crate::search::{ProbCut, Search, SearchContext};
+use chess::{Board, BoardStatus, ChessMove, File, Game, GameResult, MoveGen, Rank, Square};
+use std::any::Any;
 use std::io::Read;
+use std::str::FromStr;
 use serde::{Serialize, Deserialize};
 use indicatif::ProgressBar;
-use std::time::Instant;
-
-const NUM_FEATURES: usize = 65;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Print a character-based representation of a given board.
 pub fn print_board(board: &Board) {
@@ -17,72 +31,112 @@ pub fn print_board(board: &Board) {
             let piece = board.piece_on(square);
             let color = board.color_on(square);
 
-            if piece == None {
-                print!(". ");
-            } else {
-                print!("{} ", piece.unwrap().to_string(color.unwrap()));
+            match piece {
+                Some(piece) => print!("{} ", piece.to_string(color.unwrap())),
+                None => print!(". "),
             }
         }
-        println!("");
+        println!();
     }
-    println!("");
+    println!();
 }
 
-/// Return an tensor of features representing a given board.
-fn features(board: &Board) -> [f64; NUM_FEATURES] {
-    let mut features = [0.0; NUM_FEATURES];
-    features[NUM_FEATURES - 1] = if board.side_to_move() == Color::White {
-        1.0
-    } else {
-        -1.0
-    };
+/// Read a set of positions from a file containing one FEN string per line.
+pub fn read_positions(filename: &str) -> Vec<Board> {
+    let contents = std::fs::read_to_string(filename).unwrap();
 
-    for i in 0..8 {
-        for j in 0..8 {
-            let square = Square::make_square(Rank::from_index(i), File::from_index(j));
-            let piece = board.piece_on(square);
-            let color = board.color_on(square);
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| Board::from_str(line).unwrap())
+        .collect()
+}
 
-            if piece != None {
-                features[i * 8 + j] = (piece.unwrap().to_index() as f64 + 1.0)
-                    * if color.unwrap() == Color::White {
-                        1.0
-                    } else {
-                        -1.0
-                    };
-            }
-        }
-    }
+/// Return the halfmove clock of a FEN string, or zero if it has none.
+pub fn fen_halfmove_clock(fen: &str) -> u16 {
+    fen.split_whitespace().nth(4).and_then(|s| s.parse().ok()).unwrap_or(0)
+}
 
-    features
+/// Read a set of positions from a file containing one FEN string per line, each with
+/// its halfmove clock.
+pub fn read_positions_with_clocks(filename: &str) -> Vec<(Board, u16)> {
+    let contents = std::fs::read_to_string(filename).unwrap();
+
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| (Board::from_str(line).unwrap(), fen_halfmove_clock(line)))
+        .collect()
 }
 
-fn evaluator_nn() -> FeedForward {
-    FeedForward::new(&[NUM_FEATURES as i32, 32, 1])
+/// The moves considered at the root of a search.
+#[derive(Clone, Debug)]
+pub enum RootMoves {
+    All,
+    Only(Vec<ChessMove>),
+    Exclude(Vec<ChessMove>),
 }
 
-#[derive(Serialize, Deserialize)]
-pub enum PruningType {
-    None,
-    AlphaBeta,
-    ProbCut,
+impl RootMoves {
+    /// Return true if the given root move may be searched.
+    pub fn allows(&self, m: ChessMove) -> bool {
+        match self {
+            RootMoves::All => true,
+            RootMoves::Only(moves) => moves.contains(&m),
+            RootMoves::Exclude(moves) => !moves.contains(&m),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Engine {
     search_depth: u8, // in ply
-    pruning_type: PruningType,
-    eval_nn: FeedForward,
+    search: Box<dyn Search>,
+    evaluator: Box<dyn Evaluator>,
+    #[serde(default)]
+    contempt: f64,
+    #[serde(default)]
+    eval_cache: EvalCache,
+    #[serde(default)]
+    wdl: WdlModel,
+    #[serde(default)]
+    training_target: TrainingTarget,
+    #[serde(default)]
+    progress: TrainingProgress,
+    #[serde(default)]
+    exploration: Exploration,
+    #[serde(skip)]
+    rng: Option<StdRng>,
+    #[serde(skip)]
+    checkpoints: Option<Checkpoints>,
+    #[serde(skip)]
+    history: History,
 }
 
 impl Engine {
-    /// Create a new engine with the given search depth and an untrained evaluator
-    /// neural network.
-    pub fn new(search_depth: u8, pruning_type: PruningType) -> Engine {
+    /// Create a new engine with the given search depth and search algorithm, and an
+    /// untrained evaluator neural network with the given specification.
+    pub fn new(search_depth: u8, search: Box<dyn Search>, spec: NetworkSpec) -> Engine {
+        Engine::with_evaluator(search_depth, search, Box::new(Network::new(spec)))
+    }
+
+    /// Create a new engine from the given search depth, search algorithm and evaluator.
+    pub fn with_evaluator(search_depth: u8, search: Box<dyn Search>, evaluator: Box<dyn Evaluator>) -> Engine {
         Engine {
-            search_depth: search_depth,
-            pruning_type: pruning_type,
-            eval_nn: evaluator_nn(),
+            search_depth,
+            search,
+            evaluator,
+            contempt: 0.0,
+            eval_cache: EvalCache::default(),
+            wdl: WdlModel::default(),
+            training_target: TrainingTarget::default(),
+            progress: TrainingProgress::default(),
+            exploration: Exploration::default(),
+            rng: None,
+            checkpoints: None,
+            history: History::default(),
         }
     }
 
@@ -91,7 +145,30 @@ impl Engine {
         let mut file = std::fs::File::open(filename).unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
-        let engine: Engine = serde_json::from_str(&contents).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&contents).unwrap();
+
+        // models saved before searches were pluggable name their search by a pruning type
+        if let Some(pruning_type) = value.get("pruning_type").cloned() {
+            let search_depth = value["search_depth"].as_u64().unwrap() as u8;
+            value["search"] = match pruning_type.as_str().unwrap() {
+                "None" => serde_json::json!({ "type": "Unpruned" }),
+                "AlphaBeta" => serde_json::json!({ "type": "AlphaBeta" }),
+                _ => {
+                    let mut probcut = match value.get("probcut") {
+                        Some(probcut) => probcut.clone(),
+                        None => serde_json::to_value(ProbCut::for_depth(search_depth)).unwrap(),
+                    };
+                    probcut["type"] = serde_json::json!("ProbCut");
+                    probcut
+                }
+            };
+        }
+        // and models saved before evaluators were interchangeable always use a network,
+        // which has no recorded perspective and so evaluates from White's point of view
+        if let Some(eval_nn) = value.get("eval_nn").cloned() {
+            value["evaluator"] = serde_json::json!({ "type": "Network", "eval_nn": eval_nn });
+        }
+        let engine: Engine = serde_json::from_value(value).unwrap();
 
         engine
     }
@@ -102,173 +179,215 @@ impl Engine {
         std::fs::write(filename, serialized).unwrap();
     }
 
-    /// Return the evaluation of a terminal node.
-    fn evaluate_terminal(&mut self, board: &Board) -> f64 {
-        let board_features = features(board);
-        let out = self.eval_nn.calc(&board_features);
-        //println!("{:?}", out);
-
-        out[0]
+    /// Set the score of a draw for the engine, as a penalty in evaluation units.
+    pub fn set_contempt(&mut self, contempt: f64) {
+        self.contempt = contempt;
     }
 
-    /// Return the evaluation of a non-terminal node by the negamax algorithm.
-    fn evaluate_nonterminal_unpruned(&mut self, board: &Board, depth: u8) -> f64 {
-        if depth == 0 || board.status() != BoardStatus::Ongoing {
-            return self.evaluate_terminal(board)
-                * if board.side_to_move() == Color::White {
-                    1.0
-                } else {
-                    -1.0
-                };
-        }
-
-        let mut value = std::f64::NEG_INFINITY;
-        for m in MoveGen::new_legal(board) {
-            let mut temp_board = board.clone();
-            board.make_move(m, &mut temp_board);
-            value = value.max(-self.evaluate_nonterminal(&temp_board, depth - 1));
-        }
-
-        value
+    /// Return the engine's search algorithm, if it is of type `S`.
+    pub fn search_mut<S: Search>(&mut self) -> Option<&mut S> {
+        (self.search.as_mut() as &mut dyn Any).downcast_mut::<S>()
     }
 
-    /// Return the evaluation of a non-terminal node by the minimax algorithm, with
-    /// alpha-beta pruning.
-    fn evaluate_nonterminal_ab_pruned(&mut self, board: &Board, depth: u8, alpha: &mut f64, beta: &mut f64) -> f64 {
-        if depth == 0 || board.status() != BoardStatus::Ongoing {
-            return self.evaluate_terminal(board)
-                * if board.side_to_move() == Color::White {
-                    1.0
-                } else {
-                    -1.0
-                };
-        }
-
-        if board.side_to_move() == Color::White {
-            let mut value = std::f64::NEG_INFINITY;
-            for m in MoveGen::new_legal(board) {
-                let mut temp_board = board.clone();
-                board.make_move(m, &mut temp_board);
-
-                value = value.max(self.evaluate_nonterminal_ab_pruned(&temp_board, depth - 1, beta, alpha));
-                if value >= *beta {
-                    break;
-                }
-                *alpha = alpha.max(value);
-            }
+    /// Return the engine's evaluator.
+    pub fn evaluator(&mut self) -> &mut dyn Evaluator {
+        self.evaluator.as_mut()
+    }
 
-            return value;
-        } else {
-            let mut value = std::f64::INFINITY;
-            for m in MoveGen::new_legal(board) {
-                let mut temp_board = board.clone();
-                board.make_move(m, &mut temp_board);
+    /// Return the engine's evaluator, if it is of type `E`.
+    pub fn evaluator_mut<E: Evaluator>(&mut self) -> Option<&mut E> {
+        (self.evaluator.as_mut() as &mut dyn Any).downcast_mut::<E>()
+    }
 
-                value = value.min(self.evaluate_nonterminal_ab_pruned(&temp_board, depth - 1, beta, alpha));
-                if value <= *alpha {
-                    break;
-                }
-                *beta = beta.min(value);
-            }
+    /// Replace the engine's evaluator, clearing the evaluation cache.
+    pub fn set_evaluator(&mut self, evaluator: Box<dyn Evaluator>) {
+        self.evaluator = evaluator;
+        self.eval_cache.clear();
+    }
 
-            return value;
-        }
+    /// Set the number of entries in the evaluation cache, or zero to disable it.
+    pub fn set_eval_cache_size(&mut self, size: usize) {
+        self.eval_cache.resize(size);
     }
 
-    /// Return the evaluation of a non-terminal node by the minimax algorithm, with alpha-beta
-    /// pruning and the ProbCut extension.
-    fn evaluate_nonterminal_probcut_pruned(&mut self, board: &Board, depth: u8, alpha: &mut f64, beta: &mut f64) -> f64 {
-        let shallow_search_depth = self.search_depth / 2;
-        const T: f64 = 1.5; // these values taken from paper linked in plan
-        const A: f64 = 1.0;
-        const B: f64 = 0.0;
-        const STDEV: f64 = 0.5;
-
-        if depth == 0 || board.status() != BoardStatus::Ongoing {
-            return self.evaluate_terminal(board)
-                * if board.side_to_move() == Color::White {
-                    1.0
-                } else {
-                    -1.0
-                };
-        }
+    /// Return the engine's evaluation cache.
+    pub fn eval_cache(&self) -> &EvalCache {
+        &self.eval_cache
+    }
 
-        if depth == self.search_depth {
-            let mut bound = ((T * STDEV + *beta - B) / A).round();
-            let mut bound_off_one = bound - 1.0;
-            if self.evaluate_nonterminal_probcut_pruned(board, shallow_search_depth, &mut bound, &mut bound_off_one) >= bound {
-                return *beta;
-            }
+    /// Return the mapping from the engine's evaluations to win, draw and loss
+    /// probabilities and centipawns.
+    pub fn wdl(&self) -> &WdlModel {
+        &self.wdl
+    }
 
-            let mut bound = ((-T * STDEV + *alpha - B) / A).round();
-            let mut bound_off_one = bound + 1.0;
-            if self.evaluate_nonterminal_probcut_pruned(board, shallow_search_depth, &mut bound, &mut bound_off_one) <= bound {
-                return *alpha;
+    /// Fit the mapping from evaluations to win, draw and loss probabilities to the
+    /// static evaluations of the positions in `num_games` self-play games and their
+    /// results.
+    pub fn calibrate_wdl(&mut self, num_games: u32) {
+        let mut samples = Vec::new();
+        let pb = ProgressBar::new(num_games as u64);
+        for _ in 0..num_games {
+            let (winner, positions, _, _) = self.play_self(false);
+            for (board, halfmove_clock) in positions.iter() {
+                let value = self.evaluator.evaluate(board, *halfmove_clock);
+                samples.push((value, winner as f64 * side_sign(board)));
             }
+            pb.inc(1);
         }
+        pb.finish();
 
-        self.evaluate_nonterminal_ab_pruned(board, depth, alpha, beta)
+        let log_likelihood = self.wdl.fit(&samples);
+        println!(
+            "Calibration finished.\nScale: {} Draw margin: {} Mean log-likelihood: {}",
+            self.wdl.scale, self.wdl.draw_margin, log_likelihood
+        );
     }
 
-    /// Return the evaluation of a non-terminal node by the minimax algorithm, with alpha-beta
-    /// pruning and the null move heuristic extension.
-    fn evaluate_nonterminal_null_move_pruned(&mut self, board: &Board, depth: u8, alpha: &mut f64, beta: &mut f64) -> f64 {}
+    /// Print any statistics collected by the engine's search algorithm, and the hit
+    /// rate of its evaluation cache.
+    pub fn print_search_stats(&self) {
+        self.search.print_stats();
+        let (hits, misses) = self.eval_cache.stats();
+        println!(
+            "Evaluation cache: {} hits, {} misses ({:.1}% hit rate)",
+            hits,
+            misses,
+            100.0 * self.eval_cache.hit_rate()
+        );
+    }
 
-    /// Return the evaluation of a non-terminal node, using the appropriate pruning
-    /// algorithm (`self.pruning_type`).
+    /// Return the evaluation of a non-terminal node, using the engine's search algorithm.
     fn evaluate_nonterminal(&mut self, board: &Board, depth: u8) -> f64 {
-        match self.pruning_type {
-            PruningType::None => self.evaluate_nonterminal_unpruned(board, depth),
-            PruningType::AlphaBeta => {
-                let mut alpha = std::f64::NEG_INFINITY;
-                let mut beta = std::f64::INFINITY;
-
-                self.evaluate_nonterminal_ab_pruned(board, depth, &mut alpha, &mut beta)
-            },
-            PruningType::ProbCut => {
-                let mut alpha = std::f64::NEG_INFINITY;
-                let mut beta = std::f64::INFINITY;
-
-                self.evaluate_nonterminal_probcut_pruned(board, depth, &mut alpha, &mut beta)
-            },
-        }
+        let mut ctx = SearchContext {
+            evaluator: self.evaluator.as_mut(),
+            history: &mut self.history,
+            cache: &mut self.eval_cache,
+            contempt: self.contempt,
+        };
+
+        self.search.evaluate(&mut ctx, board, depth)
     }
 
     /// Public interface to the `evaluate_nonterminal` function.
     pub fn evaluate_board(&mut self, board: &Board) -> f64 {
+        self.evaluate_board_with_history(board, &History::new(board))
+    }
+
+    /// Evaluate a board reached by the given history of positions, so that repetitions
+    /// and the fifty-move rule are taken into account. The value is from the
+    /// perspective of the side to move.
+    pub fn evaluate_board_with_history(&mut self, board: &Board, history: &History) -> f64 {
+        self.history = history.clone();
+        self.history.set_root(board);
+
         self.evaluate_nonterminal(board, self.search_depth)
     }
 
-    /// Return the best move for a given board.
+    /// Fit the engine's ProbCut cut pairs to its evaluator, using the given positions.
+    /// Panics if the engine does not search with ProbCut.
+    pub fn calibrate_probcut(&mut self, positions: &[Board]) {
+        let probcut = (self.search.as_mut() as &mut dyn Any)
+            .downcast_mut::<ProbCut>()
+            .expect("engine does not use ProbCut");
+        let mut ctx = SearchContext {
+            evaluator: self.evaluator.as_mut(),
+            history: &mut self.history,
+            cache: &mut self.eval_cache,
+            contempt: self.contempt,
+        };
+
+        probcut.calibrate(&mut ctx, positions);
+    }
+
+    /// Return the best move for a given board. Panics if the game is over.
     pub fn best_move(&mut self, board: &Board, show: bool) -> ChessMove {
-        let legal_moves = MoveGen::new_legal(board);
-        let mut best_value = std::f64::NEG_INFINITY;
-        let mut best_move = ChessMove::new(Square::A1, Square::A1, None); // null move, avoids warning for uninitialised return value
+        self.best_move_with_history(board, &History::new(board), show)
+    }
+
+    /// Return the best move for a board reached by the given history of positions,
+    /// so that repetitions and the fifty-move rule are taken into account. Panics if
+    /// the game is over.
+    pub fn best_move_with_history(&mut self, board: &Board, history: &History, show: bool) -> ChessMove {
+        self.best_move_restricted(board, history, &RootMoves::All, show)
+            .expect("the position has no legal moves")
+    }
+
+    /// Return the best move for a board reached by the given history of positions,
+    /// considering only the root moves allowed by `root_moves`, or `None` if no legal
+    /// move is allowed.
+    pub fn best_move_restricted(&mut self, board: &Board, history: &History, root_moves: &RootMoves, show: bool) -> Option<ChessMove> {
+        self.search_root(board, history, root_moves, show).map(|(m, _)| m)
+    }
+
+    /// Return the best move as for `best_move_restricted`, along with its value from the
+    /// perspective of the side to move.
+    fn search_root(&mut self, board: &Board, history: &History, root_moves: &RootMoves, show: bool) -> Option<(ChessMove, f64)> {
+        let mut best: Option<(ChessMove, f64)> = None;
+        for (m, value) in self.root_values(board, history, root_moves, show) {
+            if best.is_none_or(|(_, best_value)| value > best_value) {
+                best = Some((m, value));
+            }
+        }
+
+        best
+    }
+
+    /// Return each root move allowed by `root_moves` with its value from the
+    /// perspective of the side to move.
+    fn root_values(&mut self, board: &Board, history: &History, root_moves: &RootMoves, show: bool) -> Vec<(ChessMove, f64)> {
+        self.history = history.clone();
+        self.history.set_root(board);
+
+        let legal_moves: Vec<ChessMove> = MoveGen::new_legal(board)
+            .filter(|m| root_moves.allows(*m))
+            .collect();
 
         if show {
             println!("Searching {} moves...\n", legal_moves.len());
         }
+        let mut values = Vec::with_capacity(legal_moves.len());
         for m in legal_moves {
-            let mut temp_board = board.clone();
-            board.make_move(m, &mut temp_board);
-            let value = self.evaluate_board(&temp_board);
-            if value > best_value {
-                best_value = value;
-                best_move = m;
-            }
+            let temp_board = board.make_move_new(m);
+            self.history.push(board, m, &temp_board);
+            values.push((m, -self.evaluate_nonterminal(&temp_board, self.search_depth)));
+            self.history.pop();
         }
 
-        //println!("{:?}", features(board));
+        values
+    }
+
+    /// Return how self-play games explore moves other than the best.
+    pub fn exploration(&self) -> &Exploration {
+        &self.exploration
+    }
+
+    /// Set how self-play games explore moves other than the best, and reset the random
+    /// number generator from its seed and the number of games played so far.
+    pub fn set_exploration(&mut self, exploration: Exploration) {
+        self.rng = Some(exploration.rng(self.progress.games_played));
+        self.exploration = exploration;
+    }
+
+    /// Choose the move to play at the given ply of a self-play game from the values of
+    /// the root moves, exploring according to the engine's exploration settings.
+    fn choose_self_play_move(&mut self, ply: u32, values: &[(ChessMove, f64)]) -> ChessMove {
+        let exploration = &self.exploration;
+        let games_played = self.progress.games_played;
+        let rng = self.rng.get_or_insert_with(|| exploration.rng(games_played));
 
-        best_move
+        exploration.choose(rng, ply, values)
     }
 
     /// Play a game between this engine and itself, and return +1 if white wins, -1 if
-    /// black wins, and 0 if it is a draw, along with a vector of the features of each
-    /// position from the game, and the mean time per move in nanoseconds.
-    fn play_self(&mut self, show: bool) -> (i8, Vec<[f64; NUM_FEATURES]>, u128) {
+    /// black wins, and 0 if it is a draw, along with a vector of each position from
+    /// the game with its halfmove clock, the value of the search from each position
+    /// but the last for the side to move, and the mean time per move in nanoseconds.
+    fn play_self(&mut self, show: bool) -> (i8, Vec<(Board, u16)>, Vec<f64>, u128) {
         let mut game = Game::new();
-        let mut positions = vec![features(&game.current_position())];
+        let mut history = History::new(&game.current_position());
+        let mut positions = vec![(game.current_position(), 0)];
+        let mut search_values = Vec::new();
 
         if show {
             println!("NEW GAME\n");
@@ -280,13 +399,18 @@ impl Engine {
         while game.result().is_none() {
             if game.can_declare_draw() {
                 game.declare_draw();
+                break;
             }
 
             let start_time = Instant::now();
-            let best_move = self.best_move(&game.current_position(), show);
+            let board = game.current_position();
+            let values = self.root_values(&board, &history, &RootMoves::All, show);
+            let best_move = self.choose_self_play_move(num_moves as u32, &values);
             let time_taken = start_time.elapsed().as_nanos();
+            search_values.push(values.iter().map(|(_, value)| *value).fold(f64::NEG_INFINITY, f64::max));
 
             game.make_move(best_move);
+            history.push(&board, best_move, &game.current_position());
 
             num_moves += 1;
             sum_nanos_per_move += time_taken;
@@ -295,7 +419,7 @@ impl Engine {
                 print_board(&game.current_position());
                 println!("{:?}: {}\n", !game.side_to_move(), best_move);
             }
-            positions.push(features(&game.current_position()));
+            positions.push((game.current_position(), history.halfmove_clock()));
         }
 
         let result = game.result().unwrap();
@@ -303,76 +427,385 @@ impl Engine {
             if show {
                 println!("White wins.");
             }
-            return (1, positions, sum_nanos_per_move / num_moves);
+            return (1, positions, search_values, sum_nanos_per_move / num_moves);
         }
         if result == GameResult::BlackCheckmates || result == GameResult::WhiteResigns {
             if show {
                 println!("Black wins.");
             }
-            return (-1, positions, sum_nanos_per_move / num_moves);
+            return (-1, positions, search_values, sum_nanos_per_move / num_moves);
         }
 
         if show {
             println!("Draw.");
         }
-        (0, positions, sum_nanos_per_move / num_moves)
+        (0, positions, search_values, sum_nanos_per_move / num_moves)
+    }
+
+    /// Set how the training target of each position of a self-play game is chosen.
+    pub fn set_training_target(&mut self, training_target: TrainingTarget) {
+        self.training_target = training_target;
+    }
+
+    /// Fit the evaluator to the positions of a self-play game with the given winner,
+    /// using the engine's training target, in mini-batches of `batch_size` positions.
+    /// `search_values` holds the value of the search from each position but the last,
+    /// for the side to move, which is clamped to the range of results so that a mate
+    /// found by the search counts as a win. Returns the sum of the squared errors of
+    /// the positions just before they were fitted.
+    fn fit_game(&mut self, winner: i8, positions: &[(Board, u16)], search_values: &[f64], batch_size: usize) -> f64 {
+        let result = winner as f64;
+        let num_values = positions.len() - 1;
+
+        // targets from White's point of view
+        let targets = match self.training_target {
+            TrainingTarget::MonteCarlo => vec![result; positions.len()],
+            TrainingTarget::Td { lambda } => {
+                let values: Vec<f64> = positions[..num_values]
+                    .iter()
+                    .map(|(board, halfmove_clock)| self.evaluator.evaluate(board, *halfmove_clock) * side_sign(board))
+                    .collect();
+                lambda_returns(&values, result, lambda)
+            }
+            TrainingTarget::TdLeaf { lambda } => {
+                let values: Vec<f64> = positions[..num_values]
+                    .iter()
+                    .zip(search_values)
+                    .map(|((board, _), value)| value.clamp(-1.0, 1.0) * side_sign(board))
+                    .collect();
+                lambda_returns(&values, result, lambda)
+            }
+        };
+
+        let samples: Vec<(Board, u16, f64)> = positions
+            .iter()
+            .zip(targets)
+            .map(|((board, halfmove_clock), target)| (*board, *halfmove_clock, target * side_sign(board)))
+            .collect();
+        let sum_squared_error = self.fit_samples(&samples, batch_size);
+        self.eval_cache.clear();
+
+        sum_squared_error
+    }
+
+    /// Fit the evaluator to samples of a board, its halfmove clock and a target for the
+    /// side to move, in mini-batches of `batch_size`. Returns the sum of the squared
+    /// errors of the samples just before their batch was fitted.
+    fn fit_samples(&mut self, samples: &[(Board, u16, f64)], batch_size: usize) -> f64 {
+        let mut sum_squared_error = 0.0;
+        self.evaluator.begin_training();
+        for batch in samples.chunks(batch_size.max(1)) {
+            for (board, halfmove_clock, target) in batch {
+                sum_squared_error += (self.evaluator.evaluate(board, *halfmove_clock) - target).powi(2);
+            }
+            self.evaluator.fit_batch(batch);
+        }
+        self.evaluator.end_training();
+
+        sum_squared_error
+    }
+
+    /// Return the mean squared error of the evaluator on a set of labelled positions.
+    /// Panics if there are none, since the loss would be undefined.
+    pub fn validation_loss(&mut self, positions: &[LabelledPosition]) -> f64 {
+        assert!(!positions.is_empty(), "the validation set is empty");
+        let sum_squared_error: f64 = positions
+            .iter()
+            .map(|position| {
+                let target = position.target * side_sign(&position.board);
+                (self.evaluator.evaluate(&position.board, position.halfmove_clock) - target).powi(2)
+            })
+            .sum();
+
+        sum_squared_error / positions.len() as f64
+    }
+
+    /// Record the losses of a step of training in the learning curve, keeping a copy of
+    /// the evaluator in `best` if it has the lowest validation loss so far. Returns
+    /// true if training should stop early, in which case the best evaluator has been
+    /// restored.
+    fn record_step(&mut self, curve: &mut LearningCurve, best: &mut String, step: u32, train_loss: f64, validation_loss: f64, config: &TrainingConfig) -> bool {
+        curve.push(step, train_loss, validation_loss);
+        if curve.best_step() == Some(step) {
+            *best = serde_json::to_string(&self.evaluator).unwrap();
+        }
+        if let Some(filename) = &config.curve_file {
+            curve.save_csv(filename);
+        }
+
+        match config.patience {
+            Some(patience) if curve.should_stop(patience) => {
+                println!("Stopping early, restoring the evaluator from step {}.", curve.best_step().unwrap());
+                self.evaluator = serde_json::from_str(best).unwrap();
+                self.eval_cache.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Save checkpoints of the engine while training by self-play, and save one and stop
+    /// training cleanly when Ctrl-C is pressed.
+    pub fn set_checkpoints(&mut self, mut checkpoints: Checkpoints) {
+        training::catch_interrupts();
+        checkpoints.saved(self.progress.games_played);
+        self.checkpoints = Some(checkpoints);
+    }
+
+    /// Return the counts of the self-play training done so far, including any done
+    /// before the engine was saved.
+    pub fn progress(&self) -> &TrainingProgress {
+        &self.progress
+    }
+
+    /// Count a finished self-play training game, and save a checkpoint if one is due or
+    /// training has been interrupted. Returns true if training should stop.
+    fn finish_training_game(&mut self, winner: i8, time: Duration) -> bool {
+        self.progress.record_game(winner, time);
+
+        let interrupted = training::interrupted();
+        let games_played = self.progress.games_played;
+        let filename = match &self.checkpoints {
+            Some(checkpoints) if interrupted || checkpoints.due(games_played) => checkpoints.filename.clone(),
+            _ => return interrupted,
+        };
+        self.save(&filename);
+        self.checkpoints.as_mut().unwrap().saved(games_played);
+        println!("Saved checkpoint after {} games to {}.", games_played, filename);
+
+        interrupted
+    }
+
+    /// Print the results of the self-play training games, including any played before
+    /// the engine was saved.
+    fn print_training_results(&self) {
+        let progress = &self.progress;
+        println!(
+            "Training finished.\nWhite wins: {}\nBlack wins: {}\nDraws: {}",
+            progress.white_wins, progress.black_wins, progress.draws
+        );
     }
 
     /// Trains the engine via self-play, playing the given number of games.
     pub fn train_games(&mut self, num_games: u32, show: bool) {
-        let mut white_wins = 0;
-        let mut black_wins = 0;
-        let mut draws = 0;
-
         let pb = ProgressBar::new(num_games as u64);
         for _ in 0..num_games {
             pb.inc(1);
 
-            let (winner, features, _) = self.play_self(show);
-            if winner == 1 {
-                white_wins += 1;
-            } else if winner == -1 {
-                black_wins += 1;
-            } else if winner == 0 {
-                draws += 1;
-            }
-
-            for i in 0..features.len() {
-                self.eval_nn.fit(&features[i], &[winner as f64]);
+            let game_start_time = Instant::now();
+            let (winner, positions, search_values, _) = self.play_self(show);
+            self.fit_game(winner, &positions, &search_values, 1);
+            if self.finish_training_game(winner, game_start_time.elapsed()) {
+                break;
             }
         }
         pb.finish();
-        println!("Training finished.\nWhite wins: {}\nBlack wins: {}\nDraws: {}", white_wins, black_wins, draws);
+        self.print_training_results();
     }
 
     /// Trains the engine via self-play, playing for the given number of seconds.
     pub fn train_secs(&mut self, secs: u32, show: bool) {
-        let mut white_wins = 0;
-        let mut black_wins = 0;
-        let mut draws = 0;
-
         let pb = ProgressBar::new(secs as u64);
         let start_time = Instant::now();
         while start_time.elapsed().as_secs() < secs as u64 {
-            let inner_start_time = Instant::now();
-
-            let (winner, features, _) = self.play_self(show);
-            if winner == 1 {
-                white_wins += 1;
-            } else if winner == -1 {
-                black_wins += 1;
-            } else if winner == 0 {
-                draws += 1;
+            let game_start_time = Instant::now();
+            let (winner, positions, search_values, _) = self.play_self(show);
+            self.fit_game(winner, &positions, &search_values, 1);
+            if self.finish_training_game(winner, game_start_time.elapsed()) {
+                break;
             }
 
-            for i in 0..features.len() {
-                self.eval_nn.fit(&features[i], &[winner as f64]);
+            pb.set_position(start_time.elapsed().as_secs().min(secs as u64));
+        }
+        pb.finish();
+        self.print_training_results();
+    }
+
+    /// Play `num_games` self-play games on `workers` threads, each with its own frozen
+    /// copy of the engine as it is now, and pass each game to `consume` as it finishes,
+    /// with its winner, positions, search values and length in time. No more games
+    /// are started once `consume` returns true. Seeded exploration gives each worker a
+    /// seed drawn from the engine's own generator, so that their games differ.
+    fn play_self_parallel<F>(&mut self, num_games: u32, workers: usize, mut consume: F)
+    where
+        F: FnMut(&mut Engine, i8, Vec<(Board, u16)>, Vec<f64>, Duration) -> bool,
+    {
+        let snapshot = serde_json::to_string(&self).unwrap();
+        let explorations: Vec<Exploration> = (0..workers.max(1))
+            .map(|_| {
+                let mut exploration = self.exploration.clone();
+                if exploration.seed.is_some() {
+                    let rng = self.rng.get_or_insert_with(|| self.exploration.rng(self.progress.games_played));
+                    exploration.seed = Some(rng.gen());
+                }
+                exploration
+            })
+            .collect();
+
+        let games_started = AtomicU32::new(0);
+        let stop = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for exploration in explorations {
+                let sender = sender.clone();
+                let (snapshot, games_started, stop) = (&snapshot, &games_started, &stop);
+                scope.spawn(move || {
+                    let mut engine: Engine = serde_json::from_str(snapshot).unwrap();
+                    engine.set_exploration(exploration);
+                    while !stop.load(Ordering::Relaxed) && games_started.fetch_add(1, Ordering::Relaxed) < num_games {
+                        let game_start_time = Instant::now();
+                        let (winner, positions, search_values, _) = engine.play_self(false);
+                        let time = game_start_time.elapsed();
+                        if sender.send((winner, positions, search_values, time)).is_err() {
+                            break;
+                        }
+                    }
+                });
             }
+            drop(sender);
+
+            for (winner, positions, search_values, time) in receiver {
+                if consume(self, winner, positions, search_values, time) {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        });
+    }
 
-            pb.inc(inner_start_time.elapsed().as_secs() as u64);
+    /// Trains the engine via self-play, playing the given number of games on `workers`
+    /// threads. The workers play with frozen copies of the engine, which are refreshed
+    /// from the engine being trained every `games_per_sync` games, while the games they
+    /// finish are fitted as they arrive.
+    pub fn train_games_parallel(&mut self, num_games: u32, workers: usize, games_per_sync: u32) {
+        let pb = ProgressBar::new(num_games as u64);
+        let mut games_played = 0;
+        let mut stopped = false;
+        while games_played < num_games && !stopped {
+            let num_sync_games = games_per_sync.max(1).min(num_games - games_played);
+            self.play_self_parallel(num_sync_games, workers, |engine, winner, positions, search_values, time| {
+                pb.inc(1);
+                engine.fit_game(winner, &positions, &search_values, 1);
+                stopped = engine.finish_training_game(winner, time);
+                stopped
+            });
+            games_played += num_sync_games;
         }
         pb.finish();
-        println!("Training finished.\nWhite wins: {}\nBlack wins: {}\nDraws: {}", white_wins, black_wins, draws);
+        self.print_training_results();
+    }
+
+    /// Play `num_games` self-play games on `workers` threads without training, and add
+    /// their positions to the sample file `filename`, so that they can be reused.
+    pub fn generate_samples(&mut self, num_games: u32, workers: usize, filename: &str) {
+        let mut writer = SampleWriter::append(filename);
+        let pb = ProgressBar::new(num_games as u64);
+        self.play_self_parallel(num_games, workers, |_, winner, positions, search_values, _| {
+            writer.write_game(winner, &positions, &search_values);
+            pb.inc(1);
+            false
+        });
+        writer.flush();
+        pb.finish();
+    }
+
+    /// Set the score of each sample to the value of a search of the given depth from
+    /// its position, for the side to move, searching on `workers` threads with copies of
+    /// the engine. The last position of each game keeps its score, since it may have
+    /// ended the game by a rule that the search cannot see without the game's history.
+    pub fn label_samples(&mut self, samples: &mut [Sample], depth: u8, workers: usize) {
+        // a game ends where the next sample does not continue it
+        let game_ends: Vec<bool> = (0..samples.len())
+            .map(|i| i + 1 == samples.len() || samples[i + 1].ply != samples[i].ply + 1)
+            .collect();
+
+        let snapshot = serde_json::to_string(&self).unwrap();
+        let pb = ProgressBar::new(samples.len() as u64);
+        let chunk_size = samples.len().div_ceil(workers.max(1)).max(1);
+        thread::scope(|scope| {
+            for (chunk, chunk_game_ends) in samples.chunks_mut(chunk_size).zip(game_ends.chunks(chunk_size)) {
+                let (snapshot, pb) = (&snapshot, &pb);
+                scope.spawn(move || {
+                    let mut engine: Engine = serde_json::from_str(snapshot).unwrap();
+                    engine.search_depth = depth;
+                    for (sample, &game_end) in chunk.iter_mut().zip(chunk_game_ends) {
+                        if !game_end && sample.board.status() == BoardStatus::Ongoing {
+                            let history = History::with_halfmove_clock(&sample.board, sample.halfmove_clock);
+                            sample.score = engine.evaluate_board_with_history(&sample.board, &history) as f32;
+                        }
+                        pb.inc(1);
+                    }
+                });
+            }
+        });
+        pb.finish();
+    }
+
+    /// Trains the engine's evaluator on a set of labelled positions for up to the given
+    /// number of epochs, holding out a proportion of them for validation and shuffling
+    /// the rest before each epoch, and returns the learning curve. The training loss of
+    /// an epoch is measured on each batch just before fitting it.
+    pub fn train_labelled(&mut self, mut positions: Vec<LabelledPosition>, epochs: u32, config: &TrainingConfig) -> LearningCurve {
+        let mut rng = rand::thread_rng();
+        positions.shuffle(&mut rng);
+        // hold out at least one position, so that the validation loss is defined
+        assert!(positions.len() >= 2, "training needs at least two positions, one of them for validation");
+        let num_validation = ((positions.len() as f64 * config.validation_fraction).round() as usize).clamp(1, positions.len() - 1);
+        let validation = positions.split_off(positions.len() - num_validation);
+
+        let mut curve = LearningCurve::default();
+        let mut best = String::new();
+        let pb = ProgressBar::new(epochs as u64);
+        for epoch in 1..=epochs {
+            positions.shuffle(&mut rng);
+            let samples: Vec<(Board, u16, f64)> = positions
+                .iter()
+                .map(|position| (position.board, position.halfmove_clock, position.target * side_sign(&position.board)))
+                .collect();
+
+            let train_loss = self.fit_samples(&samples, config.batch_size) / samples.len() as f64;
+            self.eval_cache.clear();
+            let validation_loss = self.validation_loss(&validation);
+            pb.inc(1);
+            if self.record_step(&mut curve, &mut best, epoch, train_loss, validation_loss, config) {
+                break;
+            }
+        }
+        pb.finish();
+
+        curve
+    }
+
+    /// Trains the engine via self-play for up to the given number of games, measuring
+    /// the training loss of the positions played and the loss on a set of validation
+    /// positions after each round of games, and returns the learning curve.
+    pub fn train_games_validated(&mut self, num_games: u32, validation: &[LabelledPosition], config: &TrainingConfig) -> LearningCurve {
+        let mut curve = LearningCurve::default();
+        let mut best = String::new();
+        let pb = ProgressBar::new(num_games as u64);
+        let mut games_played = 0;
+        let mut round = 0;
+        while games_played < num_games {
+            let mut sum_squared_error = 0.0;
+            let mut num_positions = 0;
+            for _ in 0..config.games_per_round.min(num_games - games_played) {
+                let (winner, positions, search_values, _) = self.play_self(false);
+                sum_squared_error += self.fit_game(winner, &positions, &search_values, config.batch_size);
+                num_positions += positions.len();
+                games_played += 1;
+                pb.inc(1);
+            }
+
+            round += 1;
+            let train_loss = sum_squared_error / num_positions as f64;
+            let validation_loss = self.validation_loss(validation);
+            if self.record_step(&mut curve, &mut best, round, train_loss, validation_loss, config) {
+                break;
+            }
+        }
+        pb.finish();
+
+        curve
     }
 
     /// Trains the engine for the given number of games and then saves it to a file.
@@ -393,7 +826,7 @@ impl Engine {
         let mut sum_nanos_per_move = 0;
         let pb = ProgressBar::new(num_games as u64);
         for _ in 0..num_games {
-            let (_, _, nanos_per_move) = self.play_self(false);
+            let (_, _, _, nanos_per_move) = self.play_self(false);
             sum_nanos_per_move += nanos_per_move;
             pb.inc(1);
         }
@@ -0,0 +1,623 @@
+#![allow(dead_code)]
+
+use crate::dense::is_activated;
+use crate::features::FeatureSet;
+use chess::{Board, Color, Piece, ALL_PIECES};
+use neuroflow::{activators, FeedForward};
+use serde::{Serialize, Deserialize};
+use std::any::Any;
+use std::str::FromStr;
+
+/// A static evaluation of positions, used by an engine at the leaves of its search.
+/// Implementations are saved with the engine, tagged with their type name.
+#[typetag::serde(tag = "type")]
+pub trait Evaluator: Any {
+    /// Return the evaluation of `board`, positive when the side to move is better, where
+    /// `halfmove_clock` is the number of plies since the last capture or pawn move.
+    fn evaluate(&mut self, board: &Board, halfmove_clock: u16) -> f64;
+
+    /// Return true if the evaluation depends on the halfmove clock, so that cached
+    /// evaluations must be keyed by it as well as by the position.
+    fn uses_halfmove_clock(&self) -> bool {
+        true
+    }
+
+    /// Move the evaluation of `board` towards `target`, from the perspective of the
+    /// side to move. Evaluators without learnable parameters ignore this.
+    fn fit(&mut self, _board: &Board, _halfmove_clock: u16, _target: f64) {}
+
+    /// Move the evaluations of a mini-batch of boards, each given with its halfmove
+    /// clock, towards their targets. By default each sample is fitted in turn;
+    /// evaluators that can average their updates over the batch override this.
+    fn fit_batch(&mut self, samples: &[(Board, u16, f64)]) {
+        for (board, halfmove_clock, target) in samples {
+            self.fit(board, *halfmove_clock, *target);
+        }
+    }
+
+    /// Called before a run of training by `fit_batch`, so that evaluators can keep
+    /// their parameters in a form that is faster to train until `end_training`.
+    fn begin_training(&mut self) {}
+
+    /// Called after a run of training begun by `begin_training`.
+    fn end_training(&mut self) {}
+
+    /// Called when the search makes a move from `board` to `new_board`, so that
+    /// evaluators can update their state incrementally.
+    fn make_move(&mut self, _board: &Board, _new_board: &Board) {}
+
+    /// Called when the search takes back the last move it made.
+    fn unmake_move(&mut self) {}
+}
+
+/// An activation function of the hidden layers of a network.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Activation {
+    #[default]
+    Tanh,
+    Sigmoid,
+}
+
+impl FromStr for Activation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Activation, String> {
+        match s {
+            "tanh" => Ok(Activation::Tanh),
+            "sigmoid" => Ok(Activation::Sigmoid),
+            _ => Err(format!("unknown activation {}, expected tanh or sigmoid", s)),
+        }
+    }
+}
+
+/// An activation function or its derivative.
+type Activator = fn(f64) -> f64;
+
+impl Activation {
+    /// Apply the function to `x`, exactly as neuroflow does.
+    pub fn apply(self, x: f32) -> f32 {
+        match self {
+            Activation::Tanh => x.tanh(),
+            Activation::Sigmoid => 1.0 / (1.0 + x.exp()),
+        }
+    }
+
+    fn to_neuroflow(self) -> activators::Type {
+        match self {
+            Activation::Tanh => activators::Type::Tanh,
+            Activation::Sigmoid => activators::Type::Sigmoid,
+        }
+    }
+
+    /// Return neuroflow's function and the derivative it trains with.
+    fn neuroflow_functions(self) -> (Activator, Activator) {
+        match self {
+            Activation::Tanh => (activators::tanh, activators::der_tanh),
+            Activation::Sigmoid => (activators::sigm, activators::der_sigm),
+        }
+    }
+}
+
+/// The point of view of the outputs of a network, which is saved with it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Perspective {
+    /// The network sees the board as it is and its output is positive when White is
+    /// better. Networks saved before the perspective was recorded were trained this way.
+    #[default]
+    White,
+    /// The network sees the board mirrored when Black is to move, and its output is
+    /// positive when the side to move is better.
+    SideToMove,
+}
+
+impl Perspective {
+    /// Return the inputs of a network with this perspective taking the given set of
+    /// features for a board, and the sign that turns its output into a value for the
+    /// side to move.
+    pub fn inputs(self, feature_set: FeatureSet, board: &Board, halfmove_clock: u16) -> (Vec<f64>, f64) {
+        match self {
+            Perspective::SideToMove => (feature_set.features(board, halfmove_clock), 1.0),
+            Perspective::White => (feature_set.white_features(board, halfmove_clock), side_sign(board)),
+        }
+    }
+}
+
+/// The architecture and training parameters of a network. The output layer is a single
+/// linear neuron; every hidden layer uses the same activation function.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NetworkSpec {
+    #[serde(default)]
+    pub feature_set: FeatureSet,
+    #[serde(default = "NetworkSpec::default_hidden_sizes")]
+    pub hidden_sizes: Vec<usize>,
+    #[serde(default)]
+    pub activation: Activation,
+    #[serde(default = "NetworkSpec::default_learning_rate")]
+    pub learning_rate: f64,
+    #[serde(default)]
+    pub perspective: Perspective,
+}
+
+impl NetworkSpec {
+    /// Return the default specification for a network taking the given set of features:
+    /// one hidden layer of 32 tanh neurons, with neuroflow's default learning rate,
+    /// evaluating from the perspective of the side to move.
+    pub fn new(feature_set: FeatureSet) -> NetworkSpec {
+        NetworkSpec {
+            feature_set,
+            hidden_sizes: NetworkSpec::default_hidden_sizes(),
+            activation: Activation::default(),
+            learning_rate: NetworkSpec::default_learning_rate(),
+            perspective: Perspective::SideToMove,
+        }
+    }
+
+    /// Return the specification with the given hidden layer sizes.
+    pub fn hidden_sizes(mut self, hidden_sizes: &[usize]) -> NetworkSpec {
+        self.hidden_sizes = hidden_sizes.to_vec();
+        self
+    }
+
+    /// Return the specification with the given activation function.
+    pub fn activation(mut self, activation: Activation) -> NetworkSpec {
+        self.activation = activation;
+        self
+    }
+
+    /// Return the specification with the given learning rate.
+    pub fn learning_rate(mut self, learning_rate: f64) -> NetworkSpec {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Return the number of neurons in each layer, from the inputs to the output.
+    pub fn architecture(&self) -> Vec<i32> {
+        let mut architecture = vec![self.feature_set.num_features() as i32];
+        architecture.extend(self.hidden_sizes.iter().map(|&size| size as i32));
+        architecture.push(1);
+
+        architecture
+    }
+
+    fn default_hidden_sizes() -> Vec<usize> {
+        vec![32]
+    }
+
+    fn default_learning_rate() -> f64 {
+        0.1
+    }
+}
+
+impl Default for NetworkSpec {
+    fn default() -> NetworkSpec {
+        NetworkSpec::new(FeatureSet::default())
+    }
+}
+
+/// Evaluation by a feed-forward neural network over the features of the board, which
+/// are taken from the perspective of the side to move.
+#[derive(Serialize, Deserialize)]
+pub struct Network {
+    #[serde(flatten)]
+    spec: NetworkSpec,
+    eval_nn: FeedForward,
+    #[serde(skip)]
+    configured: bool, // neuroflow does not restore the activation function when loading
+    #[serde(skip)]
+    training: Option<TrainingWeights>,
+}
+
+impl Network {
+    /// Create an untrained network with the given specification.
+    pub fn new(spec: NetworkSpec) -> Network {
+        let eval_nn = FeedForward::new(&spec.architecture());
+        Network::from_nn(spec, eval_nn)
+    }
+
+    /// Create an evaluator from an existing network, which must have the architecture
+    /// given by `spec`.
+    pub fn from_nn(spec: NetworkSpec, eval_nn: FeedForward) -> Network {
+        Network { spec, eval_nn, configured: false, training: None }
+    }
+
+    /// Return the specification of the network.
+    pub fn spec(&self) -> &NetworkSpec {
+        &self.spec
+    }
+
+    /// Return the set of features the network takes.
+    pub fn feature_set(&self) -> FeatureSet {
+        self.spec.feature_set
+    }
+
+    /// Return the inputs of the network for a board, and the sign that turns the output
+    /// of the network into a value for the side to move.
+    fn inputs(&self, board: &Board, halfmove_clock: u16) -> (Vec<f64>, f64) {
+        self.spec.perspective.inputs(self.spec.feature_set, board, halfmove_clock)
+    }
+
+    /// Apply the activation function and training parameters of the specification to
+    /// the network, if this has not been done since it was created or loaded.
+    fn configure(&mut self) {
+        if !self.configured {
+            self.eval_nn
+                .activation(self.spec.activation.to_neuroflow())
+                .learning_rate(self.spec.learning_rate);
+            self.configured = true;
+        }
+    }
+}
+
+impl Default for Network {
+    fn default() -> Network {
+        Network::new(NetworkSpec::default())
+    }
+}
+
+#[typetag::serde]
+impl Evaluator for Network {
+    fn evaluate(&mut self, board: &Board, halfmove_clock: u16) -> f64 {
+        self.configure();
+        let (board_features, sign) = self.inputs(board, halfmove_clock);
+        if let Some(weights) = &self.training {
+            return weights.calc(&board_features, self.spec.activation) * sign;
+        }
+        let out = self.eval_nn.calc(&board_features);
+
+        out[0] * sign
+    }
+
+    fn uses_halfmove_clock(&self) -> bool {
+        self.spec.feature_set.uses_halfmove_clock()
+    }
+
+    fn fit(&mut self, board: &Board, halfmove_clock: u16, target: f64) {
+        if self.training.is_some() {
+            return self.fit_batch(&[(*board, halfmove_clock, target)]);
+        }
+        self.configure();
+        let (board_features, sign) = self.inputs(board, halfmove_clock);
+        self.eval_nn.fit(&board_features, &[target * sign]);
+    }
+
+    /// Take one step of gradient descent on the mean loss over the batch, with every
+    /// gradient computed from the weights before the step. Each gradient is the one
+    /// neuroflow's `fit` steps along, so a batch of one sample trains exactly as `fit`.
+    fn fit_batch(&mut self, samples: &[(Board, u16, f64)]) {
+        if self.training.is_none() {
+            self.begin_training();
+            self.fit_batch(samples);
+            return self.end_training();
+        }
+
+        let inputs: Vec<(Vec<f64>, f64)> = samples
+            .iter()
+            .map(|(board, halfmove_clock, target)| {
+                let (board_features, sign) = self.inputs(board, *halfmove_clock);
+                (board_features, target * sign)
+            })
+            .collect();
+        let weights = self.training.as_mut().unwrap();
+        weights.step(&inputs, self.spec.activation, self.spec.learning_rate);
+    }
+
+    fn begin_training(&mut self) {
+        self.training = Some(TrainingWeights::read(&self.eval_nn));
+    }
+
+    fn end_training(&mut self) {
+        if let Some(weights) = self.training.take() {
+            self.eval_nn = weights.write();
+            self.configured = false;
+        }
+    }
+}
+
+/// The weights of a neuroflow network while it is trained in batches, which neuroflow
+/// keeps private. They are read once from its serialized form, where each neuron's
+/// weights are a row led by its bias, and written back when training ends.
+struct TrainingWeights {
+    serialized: serde_json::Value,
+    layers: Vec<Vec<Vec<f64>>>,
+}
+
+impl TrainingWeights {
+    fn read(eval_nn: &FeedForward) -> TrainingWeights {
+        let serialized = serde_json::to_value(eval_nn).unwrap();
+        let layers = serialized["layers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|layer| serde_json::from_value(layer["w"].clone()).unwrap())
+            .collect();
+
+        TrainingWeights { serialized, layers }
+    }
+
+    fn write(mut self) -> FeedForward {
+        for (j, layer) in self.layers.iter().enumerate() {
+            self.serialized["layers"][j]["w"] = serde_json::json!(layer);
+        }
+        serde_json::from_value(self.serialized).unwrap()
+    }
+
+    /// Return the inputs of each layer, led by a 1 for the bias, then the outputs of
+    /// the network led by a 1, along with the weighted sums of each layer.
+    fn forward(&self, features: &[f64], function: Activator) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+        let mut inputs = vec![[&[1.0], features].concat()];
+        let mut sums = Vec::with_capacity(self.layers.len());
+        for (j, layer) in self.layers.iter().enumerate() {
+            let layer_sums: Vec<f64> = layer
+                .iter()
+                .map(|row| row.iter().zip(&inputs[j]).map(|(w, x)| w * x).sum())
+                .collect();
+            let mut outputs = vec![1.0];
+            outputs.extend(layer_sums.iter().map(|&sum| if is_activated(j, self.layers.len()) { function(sum) } else { sum }));
+            inputs.push(outputs);
+            sums.push(layer_sums);
+        }
+
+        (inputs, sums)
+    }
+
+    /// Return the output of the network for the given features, exactly as neuroflow.
+    fn calc(&self, features: &[f64], activation: Activation) -> f64 {
+        let (function, _) = activation.neuroflow_functions();
+        self.forward(features, function).0[self.layers.len()][1]
+    }
+
+    /// Take one step of gradient descent on the mean loss over a batch of features,
+    /// each with its target output.
+    fn step(&mut self, samples: &[(Vec<f64>, f64)], activation: Activation, learning_rate: f64) {
+        let (function, derivative) = activation.neuroflow_functions();
+        let num_layers = self.layers.len();
+        let step_size = learning_rate / samples.len() as f64;
+        let mut steps: Vec<Vec<Vec<f64>>> = self
+            .layers
+            .iter()
+            .map(|layer| layer.iter().map(|row| vec![0.0; row.len()]).collect())
+            .collect();
+
+        for (features, target) in samples {
+            let (inputs, sums) = self.forward(features, function);
+
+            // as in neuroflow, the derivative of the activation is applied to the
+            // output too, even though the output layer is linear
+            let output = inputs[num_layers][1];
+            let mut deltas = vec![(target - output) * derivative(sums[num_layers - 1][0])];
+            for j in (0..num_layers).rev() {
+                for (i, delta) in deltas.iter().enumerate() {
+                    for (step, x) in steps[j][i].iter_mut().zip(&inputs[j]) {
+                        *step += step_size * delta * x;
+                    }
+                }
+                if j > 0 {
+                    deltas = (0..sums[j - 1].len())
+                        .map(|i| {
+                            let sum: f64 = deltas.iter().zip(&self.layers[j]).map(|(delta, row)| delta * row[i + 1]).sum();
+                            derivative(sums[j - 1][i]) * sum
+                        })
+                        .collect();
+                }
+            }
+        }
+
+        for (layer, layer_steps) in self.layers.iter_mut().zip(&steps) {
+            for (row, row_steps) in layer.iter_mut().zip(layer_steps) {
+                for (w, step) in row.iter_mut().zip(row_steps) {
+                    *w += step;
+                }
+            }
+        }
+    }
+}
+
+/// Return +1 if White is to move on `board` and -1 if Black is, to turn White-positive
+/// values into values for the side to move and back.
+pub fn side_sign(board: &Board) -> f64 {
+    if board.side_to_move() == Color::White {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Return the value of a piece in pawns.
+pub fn piece_value(piece: Piece) -> f64 {
+    match piece {
+        Piece::Pawn => 1.0,
+        Piece::Knight => 3.0,
+        Piece::Bishop => 3.0,
+        Piece::Rook => 5.0,
+        Piece::Queen => 9.0,
+        Piece::King => 0.0,
+    }
+}
+
+/// Evaluation by material balance alone, in pawns for the side to move.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Material;
+
+#[typetag::serde]
+impl Evaluator for Material {
+    fn evaluate(&mut self, board: &Board, _halfmove_clock: u16) -> f64 {
+        let mut value = 0.0;
+        for piece in ALL_PIECES {
+            let white = (board.pieces(piece) & board.color_combined(Color::White)).popcnt();
+            let black = (board.pieces(piece) & board.color_combined(Color::Black)).popcnt();
+            value += piece_value(piece) * (white as f64 - black as f64);
+        }
+
+        value * side_sign(board)
+    }
+
+    fn uses_halfmove_clock(&self) -> bool {
+        false
+    }
+}
+
+/// Evaluation by a weighted sum of other evaluators. Training is passed on to each of
+/// them.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Combined {
+    pub terms: Vec<(f64, Box<dyn Evaluator>)>,
+}
+
+impl Combined {
+    /// Return the combination with `evaluator` added with the given weight.
+    pub fn with(mut self, weight: f64, evaluator: Box<dyn Evaluator>) -> Combined {
+        self.terms.push((weight, evaluator));
+        self
+    }
+}
+
+#[typetag::serde]
+impl Evaluator for Combined {
+    fn evaluate(&mut self, board: &Board, halfmove_clock: u16) -> f64 {
+        self.terms
+            .iter_mut()
+            .map(|(weight, evaluator)| *weight * evaluator.evaluate(board, halfmove_clock))
+            .sum()
+    }
+
+    fn uses_halfmove_clock(&self) -> bool {
+        self.terms.iter().any(|(_, evaluator)| evaluator.uses_halfmove_clock())
+    }
+
+    fn fit(&mut self, board: &Board, halfmove_clock: u16, target: f64) {
+        for (_, evaluator) in self.terms.iter_mut() {
+            evaluator.fit(board, halfmove_clock, target);
+        }
+    }
+
+    fn fit_batch(&mut self, samples: &[(Board, u16, f64)]) {
+        for (_, evaluator) in self.terms.iter_mut() {
+            evaluator.fit_batch(samples);
+        }
+    }
+
+    fn begin_training(&mut self) {
+        for (_, evaluator) in self.terms.iter_mut() {
+            evaluator.begin_training();
+        }
+    }
+
+    fn end_training(&mut self) {
+        for (_, evaluator) in self.terms.iter_mut() {
+            evaluator.end_training();
+        }
+    }
+
+    fn make_move(&mut self, board: &Board, new_board: &Board) {
+        for (_, evaluator) in self.terms.iter_mut() {
+            evaluator.make_move(board, new_board);
+        }
+    }
+
+    fn unmake_move(&mut self) {
+        for (_, evaluator) in self.terms.iter_mut() {
+            evaluator.unmake_move();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::tests::mirrored_pairs;
+    use crate::handcrafted::Handcrafted;
+
+    fn assert_symmetric(evaluator: &mut dyn Evaluator) {
+        for (board, mirrored) in mirrored_pairs() {
+            let value = evaluator.evaluate(&board, 3);
+            let mirrored_value = evaluator.evaluate(&mirrored, 3);
+            assert!((value - mirrored_value).abs() < 1e-9, "{} != {} for {}", value, mirrored_value, board);
+        }
+    }
+
+    #[test]
+    fn material_is_colour_symmetric() {
+        assert_symmetric(&mut Material);
+    }
+
+    #[test]
+    fn handcrafted_is_colour_symmetric() {
+        assert_symmetric(&mut Handcrafted);
+    }
+
+    #[test]
+    fn networks_are_colour_symmetric() {
+        for feature_set in [FeatureSet::Compact, FeatureSet::OneHot, FeatureSet::Extended] {
+            assert_symmetric(&mut Network::new(NetworkSpec::new(feature_set)));
+        }
+    }
+
+    #[test]
+    fn network_batches_average_neuroflow_steps() {
+        let spec = NetworkSpec::default().hidden_sizes(&[8, 4]).activation(Activation::Sigmoid);
+        for (board, mirrored) in mirrored_pairs() {
+            let mut single = Network::new(spec.clone());
+            let mut batched: Network = serde_json::from_value(serde_json::to_value(&single).unwrap()).unwrap();
+
+            // a batch of copies of one sample takes the same step as fitting it once
+            single.fit(&board, 0, 0.5);
+            batched.fit_batch(&[(board, 0, 0.5), (board, 0, 0.5)]);
+            let (single_value, batched_value) = (single.evaluate(&mirrored, 0), batched.evaluate(&mirrored, 0));
+            assert!((single_value - batched_value).abs() < 1e-9, "{} != {} for {}", single_value, batched_value, board);
+        }
+    }
+
+    #[test]
+    fn networks_evaluate_alike_while_training() {
+        let spec = NetworkSpec::default().hidden_sizes(&[8, 4]);
+        let mut network = Network::new(spec);
+        let boards: Vec<Board> = mirrored_pairs().into_iter().flat_map(|(board, mirrored)| [board, mirrored]).collect();
+        let evaluate_all = |network: &mut Network| -> Vec<f64> { boards.iter().map(|board| network.evaluate(board, 0)).collect() };
+
+        let before = evaluate_all(&mut network);
+        network.begin_training();
+        let training = evaluate_all(&mut network);
+        network.fit_batch(&[(boards[0], 0, 1.0), (boards[1], 0, -0.5)]);
+        let trained = evaluate_all(&mut network);
+        network.end_training();
+        let after = evaluate_all(&mut network);
+
+        for (value, other) in before.iter().zip(&training).chain(trained.iter().zip(&after)) {
+            assert!((value - other).abs() < 1e-9, "{} != {}", value, other);
+        }
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn legacy_networks_are_white_relative() {
+        let mut value = serde_json::to_value(Network::default()).unwrap();
+        value.as_object_mut().unwrap().remove("perspective");
+        let mut network: Network = serde_json::from_value(value).unwrap();
+        assert_eq!(network.spec().perspective, Perspective::White);
+
+        for (board, mirrored) in mirrored_pairs() {
+            for board in [board, mirrored] {
+                let value = network.evaluate(&board, 0);
+                let white_value = network.eval_nn.calc(&FeatureSet::Compact.white_features(&board, 0))[0];
+                assert_eq!(value, white_value * side_sign(&board), "for {}", board);
+            }
+        }
+    }
+
+    #[test]
+    fn combined_is_colour_symmetric() {
+        let mut combined = Combined::default()
+            .with(0.5, Box::new(Material))
+            .with(2.0, Box::new(Network::default()));
+        assert_symmetric(&mut combined);
+    }
+
+    #[test]
+    fn material_is_relative_to_side_to_move() {
+        for (board, _) in mirrored_pairs() {
+            if let Some(passed) = board.null_move() {
+                assert_eq!(Material.evaluate(&board, 0), -Material.evaluate(&passed, 0));
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+#![allow(dead_code)]
+
+use crate::eval::{side_sign, Combined, Evaluator};
+use crate::handcrafted::Handcrafted;
+use crate::wdl::WdlModel;
+use chess::{Board, BoardBuilder, CastleRights, File, Piece, Rank, Square};
+use std::any::Any;
+
+/// Return `board` with the piece on `square` removed, along with any castling right
+/// that depended on it, or `None` if the result is not a legal position (for example
+/// because the piece was shielding the king of the side not to move from check).
+fn without_piece(board: &Board, square: Square) -> Option<Board> {
+    let color = board.color_on(square)?;
+    let mut builder = BoardBuilder::from(board);
+    builder.clear_square(square);
+    let castle_rights = board.castle_rights(color).remove(CastleRights::square_to_castle_rights(color, square));
+    builder.castle_rights(color, castle_rights);
+    if board.en_passant() == Some(square) {
+        builder.en_passant(None);
+    }
+
+    Board::try_from(&builder).ok()
+}
+
+/// Return the contribution of each piece other than the kings to the evaluation of
+/// `board`, as the change in White's evaluation when the piece is removed. Squares are
+/// indexed as by `Square::to_index`, and are `None` when empty, holding a king, or
+/// when the position without the piece is not legal.
+pub fn piece_contributions(evaluator: &mut dyn Evaluator, board: &Board, halfmove_clock: u16) -> [Option<f64>; 64] {
+    let value = evaluator.evaluate(board, halfmove_clock) * side_sign(board);
+
+    let mut contributions = [None; 64];
+    for square in *board.combined() {
+        if board.piece_on(square) == Some(Piece::King) {
+            continue;
+        }
+        if let Some(removed) = without_piece(board, square) {
+            let removed_value = evaluator.evaluate(&removed, halfmove_clock) * side_sign(&removed);
+            contributions[square.to_index()] = Some(value - removed_value);
+        }
+    }
+
+    contributions
+}
+
+/// Print a board annotated with the contribution of each piece to White's evaluation,
+/// or `?` for pieces whose removal leaves an illegal position.
+fn print_contributions(board: &Board, contributions: &[Option<f64>; 64]) {
+    for i in (0..8).rev() {
+        print!("{} ", i + 1);
+        for j in 0..8 {
+            let square = Square::make_square(Rank::from_index(i), File::from_index(j));
+            match (board.piece_on(square), board.color_on(square)) {
+                (Some(piece), Some(color)) => {
+                    let contribution = match contributions[square.to_index()] {
+                        Some(contribution) => format!("{:+.2}", contribution),
+                        None if piece == Piece::King => String::new(),
+                        None => String::from("?"),
+                    };
+                    print!("{}{:<7}", piece.to_string(color), contribution);
+                }
+                _ => print!("{:<8}", "."),
+            }
+        }
+        println!();
+    }
+    println!("  {}", ["a", "b", "c", "d", "e", "f", "g", "h"].map(|file| format!("{:<8}", file)).concat());
+    println!();
+}
+
+/// Return the weight of the handcrafted evaluation in `evaluator`: 1 if it is the
+/// handcrafted evaluator, the sum of the weights of its handcrafted terms if it is a
+/// combination with any, and `None` otherwise.
+fn handcrafted_weight(evaluator: &dyn Evaluator) -> Option<f64> {
+    let evaluator = evaluator as &dyn Any;
+    if evaluator.is::<Handcrafted>() {
+        return Some(1.0);
+    }
+    let combined = evaluator.downcast_ref::<Combined>()?;
+    let weights: Vec<f64> = combined
+        .terms
+        .iter()
+        .filter(|(_, term)| (term.as_ref() as &dyn Any).is::<Handcrafted>())
+        .map(|(weight, _)| *weight)
+        .collect();
+
+    (!weights.is_empty()).then(|| weights.iter().sum())
+}
+
+/// Explain the evaluation of `board` by `evaluator`: print its score, the contribution
+/// of each piece on an annotated board, and the terms of the handcrafted evaluation if
+/// it is part of the evaluator. Scores are in the evaluator's units, from White's point
+/// of view unless stated.
+pub fn explain(evaluator: &mut dyn Evaluator, wdl: &WdlModel, board: &Board, halfmove_clock: u16) {
+    let value = evaluator.evaluate(board, halfmove_clock);
+    println!("Evaluation: {} for the side to move, {} for White", value, value * side_sign(board));
+    println!("White: {}\n", wdl.describe(value * side_sign(board)));
+
+    println!("Contribution of each piece (change in evaluation when it is removed):");
+    let contributions = piece_contributions(evaluator, board, halfmove_clock);
+    print_contributions(board, &contributions);
+
+    let weight = match handcrafted_weight(evaluator) {
+        Some(weight) => weight,
+        None => return,
+    };
+    if weight == 1.0 {
+        println!("Handcrafted evaluation terms, in pawns:");
+    } else {
+        println!("Handcrafted evaluation terms, in pawns, weighted by {} in the evaluation:", weight);
+    }
+    let breakdown = Handcrafted.breakdown(board);
+    for (name, term) in &breakdown {
+        println!("{:<20} {:+.2}", name, term);
+    }
+    println!("{:<20} {:+.2}", "Total", breakdown.iter().map(|(_, term)| term).sum::<f64>());
+}
@@ -0,0 +1,245 @@
+#![allow(dead_code)]
+
+use chess::{Board, BoardBuilder, Color, File, Rank, Square, ALL_COLORS, ALL_PIECES};
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+
+pub const NUM_FEATURES: usize = 65;
+
+/// Return the square reflected across the middle of the board, so that rank 1 becomes
+/// rank 8.
+pub fn flip_square(square: Square) -> Square {
+    Square::make_square(Rank::from_index(7 - square.get_rank().to_index()), square.get_file())
+}
+
+/// Return the colour-flipped mirror of a board: every piece is reflected across the
+/// middle of the board and changes colour, and so do the side to move, the castling
+/// rights and the en passant square.
+pub fn mirror(board: &Board) -> Board {
+    let mut builder = BoardBuilder::new();
+    for square in *board.combined() {
+        builder.piece(flip_square(square), board.piece_on(square).unwrap(), !board.color_on(square).unwrap());
+    }
+    for color in ALL_COLORS {
+        builder.castle_rights(!color, board.castle_rights(color));
+    }
+    builder
+        .side_to_move(!board.side_to_move())
+        .en_passant(board.en_passant().map(|square| square.get_file()));
+
+    Board::try_from(&builder).unwrap()
+}
+
+/// Return an tensor of features representing a given board.
+pub fn features(board: &Board) -> [f64; NUM_FEATURES] {
+    let mut features = [0.0; NUM_FEATURES];
+    features[NUM_FEATURES - 1] = if board.side_to_move() == Color::White {
+        1.0
+    } else {
+        -1.0
+    };
+
+    for i in 0..8 {
+        for j in 0..8 {
+            let square = Square::make_square(Rank::from_index(i), File::from_index(j));
+            let piece = board.piece_on(square);
+            let color = board.color_on(square);
+
+            if let Some(piece) = piece {
+                features[i * 8 + j] = (piece.to_index() as f64 + 1.0)
+                    * if color.unwrap() == Color::White {
+                        1.0
+                    } else {
+                        -1.0
+                    };
+            }
+        }
+    }
+
+    features
+}
+
+/// The number of one-hot features: a plane of 64 squares for each piece of each colour,
+/// then the side to move, the four castling rights and the en passant file.
+pub const NUM_ONE_HOT_FEATURES: usize = 12 * 64 + 1 + 4 + 8;
+
+/// Return a one-hot encoding of a given board, with one input for each piece of each
+/// colour on each square.
+pub fn one_hot_features(board: &Board) -> Vec<f64> {
+    let mut features = vec![0.0; NUM_ONE_HOT_FEATURES];
+
+    for color in ALL_COLORS {
+        for piece in ALL_PIECES {
+            let plane = (color.to_index() * 6 + piece.to_index()) * 64;
+            for square in board.pieces(piece) & board.color_combined(color) {
+                features[plane + square.to_index()] = 1.0;
+            }
+        }
+    }
+
+    let mut i = 12 * 64;
+    if board.side_to_move() == Color::White {
+        features[i] = 1.0;
+    }
+    i += 1;
+
+    for color in ALL_COLORS {
+        let castle_rights = board.castle_rights(color);
+        if castle_rights.has_kingside() {
+            features[i] = 1.0;
+        }
+        if castle_rights.has_queenside() {
+            features[i + 1] = 1.0;
+        }
+        i += 2;
+    }
+
+    if let Some(square) = board.en_passant() {
+        features[i + square.get_file().to_index()] = 1.0;
+    }
+
+    features
+}
+
+/// The number of extended features: the compact features, then the four castling
+/// rights, the en passant file and the halfmove clock.
+pub const NUM_EXTENDED_FEATURES: usize = NUM_FEATURES + 4 + 8 + 1;
+
+/// Return the compact features of a given board, extended with its castling rights,
+/// en passant file and halfmove clock (scaled so the fifty-move rule applies at 1).
+pub fn extended_features(board: &Board, halfmove_clock: u16) -> Vec<f64> {
+    let mut features = features(board).to_vec();
+    features.resize(NUM_EXTENDED_FEATURES, 0.0);
+
+    let mut i = NUM_FEATURES;
+    for color in ALL_COLORS {
+        let castle_rights = board.castle_rights(color);
+        if castle_rights.has_kingside() {
+            features[i] = 1.0;
+        }
+        if castle_rights.has_queenside() {
+            features[i + 1] = 1.0;
+        }
+        i += 2;
+    }
+
+    if let Some(square) = board.en_passant() {
+        features[i + square.get_file().to_index()] = 1.0;
+    }
+    i += 8;
+
+    features[i] = halfmove_clock as f64 / 100.0;
+
+    features
+}
+
+/// The versions of the features given to an evaluator network. The version is saved
+/// with the network, and models saved before it was recorded use `Compact`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum FeatureSet {
+    /// One signed scalar per square for the piece on it, and the side to move.
+    #[default]
+    Compact,
+    /// One-hot piece-square planes, the side to move, castling rights and the en
+    /// passant file.
+    OneHot,
+    /// The compact features, the castling rights, the en passant file and the
+    /// halfmove clock.
+    Extended,
+}
+
+impl FromStr for FeatureSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FeatureSet, String> {
+        match s {
+            "compact" => Ok(FeatureSet::Compact),
+            "one-hot" => Ok(FeatureSet::OneHot),
+            "extended" => Ok(FeatureSet::Extended),
+            _ => Err(format!("unknown feature set {}, expected compact, one-hot or extended", s)),
+        }
+    }
+}
+
+impl FeatureSet {
+    /// Return the number of features in the set.
+    pub fn num_features(&self) -> usize {
+        match self {
+            FeatureSet::Compact => NUM_FEATURES,
+            FeatureSet::OneHot => NUM_ONE_HOT_FEATURES,
+            FeatureSet::Extended => NUM_EXTENDED_FEATURES,
+        }
+    }
+
+    /// Return true if the features depend on the halfmove clock.
+    pub fn uses_halfmove_clock(&self) -> bool {
+        *self == FeatureSet::Extended
+    }
+
+    /// Return the features of a given board from the perspective of the side to move,
+    /// with the given number of plies since the last capture or pawn move. When Black
+    /// is to move the features are those of the mirrored board, so the network always
+    /// sees the position as White to move.
+    pub fn features(&self, board: &Board, halfmove_clock: u16) -> Vec<f64> {
+        if board.side_to_move() == Color::Black {
+            self.white_features(&mirror(board), halfmove_clock)
+        } else {
+            self.white_features(board, halfmove_clock)
+        }
+    }
+
+    /// Return the features of a given board as White sees it, whichever side is to
+    /// move, as given to networks saved before features were mirrored for Black.
+    pub fn white_features(&self, board: &Board, halfmove_clock: u16) -> Vec<f64> {
+        match self {
+            FeatureSet::Compact => features(board).to_vec(),
+            FeatureSet::OneHot => one_hot_features(board),
+            FeatureSet::Extended => extended_features(board, halfmove_clock),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Positions with pieces off their starting squares, castling rights and en passant
+    /// squares, with each side to move.
+    pub const POSITIONS: [&str; 5] = [
+        "r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/3P1N2/PPP2PPP/RNBQK2R w KQkq - 1 5",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 0 1",
+        "8/5pk1/6p1/8/3R4/6P1/5PK1/2r5 b - - 3 40",
+        "4k3/8/8/3q4/8/2N5/PP6/K7 w - - 12 60",
+    ];
+
+    /// Return each test position together with its colour-flipped mirror.
+    pub fn mirrored_pairs() -> Vec<(Board, Board)> {
+        POSITIONS
+            .iter()
+            .map(|fen| {
+                let board = Board::from_str(fen).unwrap();
+                (board, mirror(&board))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mirror_is_an_involution() {
+        for (board, mirrored) in mirrored_pairs() {
+            assert_ne!(board, mirrored);
+            assert_eq!(mirror(&mirrored), board);
+            assert_eq!(mirrored.side_to_move(), !board.side_to_move());
+        }
+    }
+
+    #[test]
+    fn features_are_colour_symmetric() {
+        for feature_set in [FeatureSet::Compact, FeatureSet::OneHot, FeatureSet::Extended] {
+            for (board, mirrored) in mirrored_pairs() {
+                assert_eq!(feature_set.features(&board, 7), feature_set.features(&mirrored, 7));
+            }
+        }
+    }
+}
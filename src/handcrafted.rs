@@ -0,0 +1,370 @@
+#![allow(dead_code)]
+
+use crate::eval::{side_sign, Evaluator};
+use chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_rook_moves, BitBoard, Board, Color, Piece, Square,
+    ALL_PIECES, EMPTY,
+};
+use serde::{Serialize, Deserialize};
+
+/// A score in centipawns, with separate middlegame and endgame values.
+#[derive(Clone, Copy, Debug, Default)]
+struct Score {
+    mg: i32,
+    eg: i32,
+}
+
+impl Score {
+    fn new(mg: i32, eg: i32) -> Score {
+        Score { mg, eg }
+    }
+
+    fn add(&mut self, other: Score, times: i32) {
+        self.mg += other.mg * times;
+        self.eg += other.eg * times;
+    }
+
+    /// Return the score in pawns, blended between its middlegame and endgame values by
+    /// the game phase, from 24 at the start of the game to 0 when only kings and pawns
+    /// remain.
+    fn taper(&self, phase: i32) -> f64 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) as f64 / MAX_PHASE as f64 / 100.0
+    }
+}
+
+const MAX_PHASE: i32 = 24;
+
+/// The material value of each piece, in the order of `ALL_PIECES`.
+const MATERIAL: [Score; 6] = [
+    Score { mg: 82, eg: 94 },
+    Score { mg: 337, eg: 281 },
+    Score { mg: 365, eg: 297 },
+    Score { mg: 477, eg: 512 },
+    Score { mg: 1025, eg: 936 },
+    Score { mg: 0, eg: 0 },
+];
+
+/// The contribution of each piece to the game phase, in the order of `ALL_PIECES`.
+const PHASE: [i32; 6] = [0, 1, 1, 2, 4, 0];
+
+// Piece-square tables, from White's point of view with the eighth rank first.
+#[rustfmt::skip]
+const PAWN_MG: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     10,  10,  20,  30,  30,  20,  10,  10,
+      5,   5,  10,  25,  25,  10,   5,   5,
+      0,   0,   0,  20,  20,   0,   0,   0,
+      5,  -5, -10,   0,   0, -10,  -5,   5,
+      5,  10,  10, -20, -20,  10,  10,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+#[rustfmt::skip]
+const PAWN_EG: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     80,  80,  80,  80,  80,  80,  80,  80,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     30,  30,  30,  30,  30,  30,  30,  30,
+     20,  20,  20,  20,  20,  20,  20,  20,
+     10,  10,  10,  10,  10,  10,  10,  10,
+     10,  10,  10,  10,  10,  10,  10,  10,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+#[rustfmt::skip]
+const KNIGHT: [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+#[rustfmt::skip]
+const BISHOP: [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+#[rustfmt::skip]
+const ROOK: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+      5,  10,  10,  10,  10,  10,  10,   5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+      0,   0,   0,   5,   5,   0,   0,   0,
+];
+#[rustfmt::skip]
+const QUEEN: [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+      0,   0,   5,   5,   5,   5,   0,  -5,
+    -10,   5,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,   0,   0,   0,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+#[rustfmt::skip]
+const KING_MG: [i32; 64] = [
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -20, -30, -30, -40, -40, -30, -30, -20,
+    -10, -20, -20, -20, -20, -20, -20, -10,
+     20,  20,   0,   0,   0,   0,  20,  20,
+     20,  30,  10,   0,   0,  10,  30,  20,
+];
+#[rustfmt::skip]
+const KING_EG: [i32; 64] = [
+    -50, -40, -30, -20, -20, -30, -40, -50,
+    -30, -20, -10,   0,   0, -10, -20, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -30,   0,   0,   0,   0, -30, -30,
+    -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+/// Return the middlegame and endgame piece-square tables of a piece.
+fn piece_square_tables(piece: Piece) -> (&'static [i32; 64], &'static [i32; 64]) {
+    match piece {
+        Piece::Pawn => (&PAWN_MG, &PAWN_EG),
+        Piece::Knight => (&KNIGHT, &KNIGHT),
+        Piece::Bishop => (&BISHOP, &BISHOP),
+        Piece::Rook => (&ROOK, &ROOK),
+        Piece::Queen => (&QUEEN, &QUEEN),
+        Piece::King => (&KING_MG, &KING_EG),
+    }
+}
+
+const DOUBLED_PAWN: Score = Score { mg: -10, eg: -20 };
+const ISOLATED_PAWN: Score = Score { mg: -10, eg: -15 };
+/// The bonus for a passed pawn, by rank from its own side.
+const PASSED_PAWN: [Score; 8] = [
+    Score { mg: 0, eg: 0 },
+    Score { mg: 5, eg: 10 },
+    Score { mg: 10, eg: 20 },
+    Score { mg: 15, eg: 35 },
+    Score { mg: 25, eg: 60 },
+    Score { mg: 40, eg: 100 },
+    Score { mg: 60, eg: 150 },
+    Score { mg: 0, eg: 0 },
+];
+const PAWN_SHIELD: Score = Score { mg: 10, eg: 0 };
+/// The penalty for each piece attacking the squares around the king, weighted by
+/// the attacking piece, in the order of `ALL_PIECES`.
+const KING_ATTACKER: [i32; 6] = [0, 2, 2, 3, 5, 0];
+/// The bonus for each square a piece can move to, in the order of `ALL_PIECES`.
+const MOBILITY: [Score; 6] = [
+    Score { mg: 0, eg: 0 },
+    Score { mg: 4, eg: 4 },
+    Score { mg: 5, eg: 5 },
+    Score { mg: 2, eg: 4 },
+    Score { mg: 1, eg: 2 },
+    Score { mg: 0, eg: 0 },
+];
+
+/// Return the index into a piece-square table of a square, for a piece of the given
+/// colour.
+fn table_index(square: Square, color: Color) -> usize {
+    let rank = square.get_rank().to_index();
+    let file = square.get_file().to_index();
+    match color {
+        Color::White => (7 - rank) * 8 + file,
+        Color::Black => rank * 8 + file,
+    }
+}
+
+/// Return the rank of a square counted from the given colour's side, from 0 to 7.
+fn relative_rank(square: Square, color: Color) -> usize {
+    match color {
+        Color::White => square.get_rank().to_index(),
+        Color::Black => 7 - square.get_rank().to_index(),
+    }
+}
+
+/// Return a bitboard of every square on the given file.
+fn file_mask(file: usize) -> BitBoard {
+    BitBoard(0x0101_0101_0101_0101 << file)
+}
+
+/// Return a bitboard of every square on the given rank.
+fn rank_mask(rank: usize) -> BitBoard {
+    BitBoard(0xff << (rank * 8))
+}
+
+/// Return a bitboard of every square on the files next to the given file.
+fn adjacent_files_mask(file: usize) -> BitBoard {
+    let mut mask = EMPTY;
+    if file > 0 {
+        mask |= file_mask(file - 1);
+    }
+    if file < 7 {
+        mask |= file_mask(file + 1);
+    }
+
+    mask
+}
+
+/// Return a bitboard of every square strictly in front of `square` from the given
+/// colour's side.
+fn squares_ahead(square: Square, color: Color) -> BitBoard {
+    let rank = square.get_rank().to_index();
+    match color {
+        Color::White if rank < 7 => BitBoard(!0u64 << ((rank + 1) * 8)),
+        Color::Black if rank > 0 => BitBoard(!0u64 >> ((8 - rank) * 8)),
+        _ => EMPTY,
+    }
+}
+
+/// Return the squares attacked by a piece other than a pawn on `square`.
+fn attacks(piece: Piece, square: Square, blockers: BitBoard) -> BitBoard {
+    match piece {
+        Piece::Knight => get_knight_moves(square),
+        Piece::Bishop => get_bishop_moves(square, blockers),
+        Piece::Rook => get_rook_moves(square, blockers),
+        Piece::Queen => get_bishop_moves(square, blockers) | get_rook_moves(square, blockers),
+        Piece::King => get_king_moves(square),
+        Piece::Pawn => EMPTY,
+    }
+}
+
+/// The terms of the handcrafted evaluation, each from White's point of view.
+#[derive(Clone, Copy, Debug, Default)]
+struct Terms {
+    material: Score,
+    piece_squares: Score,
+    pawn_structure: Score,
+    king_safety: Score,
+    mobility: Score,
+}
+
+/// A classical evaluation from material, piece-square tables, pawn structure, king
+/// safety and mobility, tapered between the middlegame and the endgame. Scores are
+/// in pawns, so it can be compared with the `Material` evaluator.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Handcrafted;
+
+impl Handcrafted {
+    /// Return the game phase of `board`, from 24 at the start of the game to 0 when
+    /// only kings and pawns remain.
+    fn phase(board: &Board) -> i32 {
+        let phase: i32 = ALL_PIECES
+            .iter()
+            .map(|&piece| PHASE[piece.to_index()] * board.pieces(piece).popcnt() as i32)
+            .sum();
+
+        phase.min(MAX_PHASE)
+    }
+
+    /// Add the terms for the pieces of one colour to `terms`, with `sign` +1 for White
+    /// and -1 for Black.
+    fn add_terms(board: &Board, color: Color, sign: i32, terms: &mut Terms) {
+        let own = *board.color_combined(color);
+        let own_pawns = board.pieces(Piece::Pawn) & own;
+        let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+        let blockers = *board.combined();
+
+        for piece in ALL_PIECES {
+            let (mg_table, eg_table) = piece_square_tables(piece);
+            for square in board.pieces(piece) & own {
+                let index = table_index(square, color);
+                terms.material.add(MATERIAL[piece.to_index()], sign);
+                terms.piece_squares.add(Score::new(mg_table[index], eg_table[index]), sign);
+
+                let moves = (attacks(piece, square, blockers) & !own).popcnt() as i32;
+                terms.mobility.add(MOBILITY[piece.to_index()], sign * moves);
+            }
+        }
+
+        // pawn structure
+        for file in 0..8 {
+            let pawns_on_file = (own_pawns & file_mask(file)).popcnt() as i32;
+            if pawns_on_file > 1 {
+                terms.pawn_structure.add(DOUBLED_PAWN, sign * (pawns_on_file - 1));
+            }
+            if pawns_on_file > 0 && (own_pawns & adjacent_files_mask(file)) == EMPTY {
+                terms.pawn_structure.add(ISOLATED_PAWN, sign * pawns_on_file);
+            }
+        }
+        for square in own_pawns {
+            let file = square.get_file().to_index();
+            let front_span = squares_ahead(square, color) & (file_mask(file) | adjacent_files_mask(file));
+            if (enemy_pawns & front_span) == EMPTY {
+                terms.pawn_structure.add(PASSED_PAWN[relative_rank(square, color)], sign);
+            }
+        }
+
+        // king safety, from the pawns sheltering the king and the enemy pieces
+        // attacking the squares around it
+        let king_square = board.king_square(color);
+        let king_file = king_square.get_file().to_index();
+        let shelter_files = file_mask(king_file) | adjacent_files_mask(king_file);
+        let king_rank = relative_rank(king_square, color);
+        let mut shelter_ranks = EMPTY;
+        for rank in (king_rank + 1)..(king_rank + 3).min(8) {
+            shelter_ranks |= rank_mask(if color == Color::White { rank } else { 7 - rank });
+        }
+        let shield_pawns = (own_pawns & shelter_files & shelter_ranks).popcnt().min(3) as i32;
+        terms.king_safety.add(PAWN_SHIELD, sign * shield_pawns);
+
+        let king_zone = get_king_moves(king_square) | BitBoard::from_square(king_square);
+        let mut attack_weight = 0;
+        for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+            for square in board.pieces(piece) & board.color_combined(!color) {
+                if (attacks(piece, square, blockers) & king_zone) != EMPTY {
+                    attack_weight += KING_ATTACKER[piece.to_index()];
+                }
+            }
+        }
+        terms.king_safety.add(Score::new(-attack_weight * attack_weight, 0), sign);
+    }
+
+    /// Return the terms of the evaluation of `board`.
+    fn terms(board: &Board) -> Terms {
+        let mut terms = Terms::default();
+        Handcrafted::add_terms(board, Color::White, 1, &mut terms);
+        Handcrafted::add_terms(board, Color::Black, -1, &mut terms);
+
+        terms
+    }
+
+    /// Return the value in pawns of each term of the evaluation of `board`, from
+    /// White's point of view. The values sum to the evaluation when White is to move,
+    /// and to its negation when Black is.
+    pub fn breakdown(&self, board: &Board) -> Vec<(&'static str, f64)> {
+        let phase = Handcrafted::phase(board);
+        let terms = Handcrafted::terms(board);
+
+        vec![
+            ("Material", terms.material.taper(phase)),
+            ("Piece-square tables", terms.piece_squares.taper(phase)),
+            ("Pawn structure", terms.pawn_structure.taper(phase)),
+            ("King safety", terms.king_safety.taper(phase)),
+            ("Mobility", terms.mobility.taper(phase)),
+        ]
+    }
+}
+
+#[typetag::serde]
+impl Evaluator for Handcrafted {
+    fn evaluate(&mut self, board: &Board, _halfmove_clock: u16) -> f64 {
+        self.breakdown(board).iter().map(|(_, value)| value).sum::<f64>() * side_sign(board)
+    }
+
+    fn uses_halfmove_clock(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,162 @@
+#![allow(dead_code)]
+
+use chess::{Board, ChessMove, Color, Piece};
+
+/// The positions reached since the start of a game, used to detect draws by
+/// threefold repetition and the fifty-move rule during search.
+#[derive(Clone)]
+pub struct History {
+    hashes: Vec<u64>,
+    clocks: Vec<u16>, // plies since the last pawn move or capture
+    irreversible: Vec<usize>, // index of the last position after a pawn move, capture or loss of castling rights
+    root: usize,
+    root_color: Color,
+}
+
+impl History {
+    /// Create a history containing only the given position.
+    pub fn new(board: &Board) -> History {
+        History {
+            hashes: vec![board.get_hash()],
+            clocks: vec![0],
+            irreversible: vec![0],
+            root: 0,
+            root_color: board.side_to_move(),
+        }
+    }
+
+    /// Create a history containing only the given position, reached after the given
+    /// number of plies without a pawn move or capture.
+    pub fn with_halfmove_clock(board: &Board, halfmove_clock: u16) -> History {
+        History {
+            clocks: vec![halfmove_clock],
+            ..History::new(board)
+        }
+    }
+
+    /// Record the move `m` from `board`, resulting in `new_board`.
+    pub fn push(&mut self, board: &Board, m: ChessMove, new_board: &Board) {
+        let current = self.hashes.len() - 1;
+        let zeroing = board.piece_on(m.get_source()) == Some(Piece::Pawn) || board.piece_on(m.get_dest()).is_some();
+        let irreversible = zeroing
+            || board.castle_rights(Color::White) != new_board.castle_rights(Color::White)
+            || board.castle_rights(Color::Black) != new_board.castle_rights(Color::Black);
+
+        self.clocks.push(if zeroing { 0 } else { self.clocks[current] + 1 });
+        self.irreversible.push(if irreversible { current + 1 } else { self.irreversible[current] });
+        self.hashes.push(new_board.get_hash());
+    }
+
+    /// Undo the last call to `push`.
+    pub fn pop(&mut self) {
+        self.hashes.pop();
+        self.clocks.pop();
+        self.irreversible.pop();
+    }
+
+    /// Mark the current position, `board`, as the root of a search.
+    pub fn set_root(&mut self, board: &Board) {
+        self.root = self.hashes.len() - 1;
+        self.root_color = board.side_to_move();
+    }
+
+    /// Return the number of plies since the last pawn move or capture, as counted by
+    /// the fifty-move rule and FEN strings.
+    pub fn halfmove_clock(&self) -> u16 {
+        self.clocks[self.clocks.len() - 1]
+    }
+
+    /// Return the side to move at the root of the current search.
+    pub fn root_color(&self) -> Color {
+        self.root_color
+    }
+
+    /// Return true if the current position is a draw by the fifty-move rule, or by
+    /// repetition. Inside the search tree a single repetition is scored as a draw,
+    /// since the side that could avoid it would already have done so. Checkmate takes
+    /// precedence over the fifty-move rule, so callers should check for it first.
+    pub fn is_draw(&self) -> bool {
+        let current = self.hashes.len() - 1;
+        if self.clocks[current] >= 100 {
+            return true;
+        }
+
+        // positions before the last irreversible move cannot be repeated
+        let first = self.irreversible[current];
+        let mut repetitions = 0;
+        let mut i = current;
+        while i >= first + 2 {
+            i -= 2;
+            if self.hashes[i] == self.hashes[current] {
+                repetitions += 1;
+                if repetitions >= 2 || i >= self.root {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new(&Board::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Play the given moves from `board`, returning the final position and its history.
+    fn play(board: &Board, moves: &[&str]) -> (Board, History) {
+        let mut board = *board;
+        let mut history = History::new(&board);
+        for m in moves {
+            let m = ChessMove::from_san(&board, m).unwrap();
+            let new_board = board.make_move_new(m);
+            history.push(&board, m, &new_board);
+            board = new_board;
+        }
+
+        (board, history)
+    }
+
+    #[test]
+    fn repetitions_are_draws() {
+        let shuffle = ["Nf3", "Nf6", "Ng1", "Ng8"];
+        let (board, mut history) = play(&Board::default(), &shuffle);
+        history.set_root(&board);
+        assert!(!history.is_draw(), "a repetition before the root is not yet a draw");
+
+        let (_, history) = play(&Board::default(), &[&shuffle[..], &shuffle[..]].concat());
+        assert!(history.is_draw(), "a threefold repetition is a draw");
+    }
+
+    #[test]
+    fn repetitions_after_the_root_are_draws() {
+        let (_, history) = play(&Board::default(), &["Nf3", "Nf6", "Ng1", "Ng8"]);
+        assert!(history.is_draw());
+    }
+
+    #[test]
+    fn castling_rights_reset_repetitions_but_not_the_clock() {
+        let board = Board::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let (_, history) = play(&board, &["Ke2", "Ke7", "Ke1", "Ke8"]);
+        assert_eq!(history.halfmove_clock(), 4);
+        assert!(!history.is_draw(), "the position with castling rights is not repeated");
+    }
+
+    #[test]
+    fn fifty_move_rule() {
+        let board = Board::from_str("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let mut history = History::with_halfmove_clock(&board, 99);
+        assert!(!history.is_draw());
+
+        let m = ChessMove::from_san(&board, "Ra2").unwrap();
+        history.push(&board, m, &board.make_move_new(m));
+        assert!(history.is_draw());
+    }
+}
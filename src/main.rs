@@ -1,12 +1,586 @@
-use chess::Color;
+use dense::NetworkFile;
+use std::collections::HashMap;
+use std::str::FromStr;
 
+mod cache;
+mod dense;
 mod engine;
+mod eval;
+mod explain;
+mod features;
+mod handcrafted;
+mod history;
+mod search;
+mod training;
+mod wdl;
 mod play;
+mod quantised;
 mod measure;
+mod nnue;
+mod samples;
+
+/// Split command arguments into positional arguments and `name=value` options, which
+/// may come in any order after them. Panics on an option not in `names`.
+fn split_options<'a>(args: &'a [String], names: &[&str]) -> (Vec<&'a String>, HashMap<&'a str, &'a str>) {
+    let (options, positional): (Vec<&String>, Vec<&String>) = args.iter().partition(|arg| arg.contains('='));
+    let options = options
+        .into_iter()
+        .map(|option| {
+            let (name, value) = option.split_once('=').unwrap();
+            assert!(names.contains(&name), "unknown option {}, expected one of {}", name, names.join(", "));
+            (name, value)
+        })
+        .collect();
+
+    (positional, options)
+}
+
+/// The options of the commands that create or train an engine, which are applied by
+/// `apply_engine_options`.
+const ENGINE_OPTIONS: [&str; 8] =
+    ["target", "contempt", "cache-size", "random-plies", "temperature", "temperature-plies", "epsilon", "seed"];
+
+/// Return the usage of the given options, for a usage line.
+fn option_usage(names: &[&str]) -> String {
+    names.iter().map(|name| format!("{}=...", name)).collect::<Vec<_>>().join(" ")
+}
+
+/// Parse the value of an option, if it is given.
+fn parse_option<T: FromStr>(options: &HashMap<&str, &str>, name: &str) -> Option<T>
+where
+    T::Err: std::fmt::Debug,
+{
+    options.get(name).map(|value| value.parse().unwrap())
+}
+
+/// Apply the options of `ENGINE_OPTIONS` that are given to an engine: its training
+/// target (`target`: `monte-carlo`, `td:λ` or `td-leaf:λ`), its contempt (`contempt`),
+/// the number of entries of its evaluation cache (`cache-size`), and how its self-play
+/// games explore, as the fields of `training::Exploration` of the same names. The
+/// exploration is only replaced, resetting its random number generator, if one of its
+/// options is given.
+fn apply_engine_options(engine: &mut engine::Engine, options: &HashMap<&str, &str>) {
+    if let Some(target) = parse_option(options, "target") {
+        engine.set_training_target(target);
+    }
+    if let Some(contempt) = parse_option(options, "contempt") {
+        engine.set_contempt(contempt);
+    }
+    if let Some(size) = parse_option(options, "cache-size") {
+        engine.set_eval_cache_size(size);
+    }
+
+    let mut exploration = engine.exploration().clone();
+    let mut explores = false;
+    if let Some(random_plies) = parse_option(options, "random-plies") {
+        exploration.random_plies = random_plies;
+        explores = true;
+    }
+    if let Some(temperature) = parse_option(options, "temperature") {
+        exploration.temperature = temperature;
+        explores = true;
+    }
+    if let Some(temperature_plies) = parse_option(options, "temperature-plies") {
+        exploration.temperature_plies = temperature_plies;
+        explores = true;
+    }
+    if let Some(epsilon) = parse_option(options, "epsilon") {
+        exploration.epsilon = epsilon;
+        explores = true;
+    }
+    if let Some(seed) = parse_option(options, "seed") {
+        exploration.seed = Some(seed);
+        explores = true;
+    }
+    if explores {
+        engine.set_exploration(exploration);
+    }
+}
+
+/// Return the evaluator described by `description`: `network`, `material` or
+/// `handcrafted`, or a weighted sum of them such as `network+0.5*handcrafted`, which is
+/// a `Combined` evaluator. Networks have the specification `spec`.
+fn parse_evaluator(description: &str, spec: &eval::NetworkSpec) -> Box<dyn eval::Evaluator> {
+    let mut terms: Vec<(f64, Box<dyn eval::Evaluator>)> = description
+        .split('+')
+        .map(|term| {
+            let (weight, name) = match term.split_once('*') {
+                Some((weight, name)) => (weight.parse().unwrap(), name),
+                None => (1.0, term),
+            };
+            let evaluator: Box<dyn eval::Evaluator> = match name {
+                "network" => Box::new(eval::Network::new(spec.clone())),
+                "material" => Box::new(eval::Material),
+                "handcrafted" => Box::new(handcrafted::Handcrafted),
+                _ => panic!("unknown evaluator {}, expected network, material or handcrafted", name),
+            };
+            (weight, evaluator)
+        })
+        .collect();
+
+    if terms.len() == 1 && terms[0].0 == 1.0 {
+        terms.pop().unwrap().1
+    } else {
+        Box::new(eval::Combined { terms })
+    }
+}
+
+/// Return the search algorithm called `name`, for searches of the given depth.
+fn parse_search(name: &str, search_depth: u8) -> Box<dyn search::Search> {
+    match name {
+        "unpruned" => Box::new(search::Unpruned),
+        "alpha-beta" => Box::new(search::AlphaBeta),
+        "probcut" => Box::new(search::ProbCut::for_depth(search_depth)),
+        _ => panic!("unknown search {}, expected unpruned, alpha-beta or probcut", name),
+    }
+}
+
+/// Create an untrained engine and save it to `args[0]`. Options choose its evaluator
+/// as by `parse_evaluator` (`evaluator`, a network by default), its search (`search`,
+/// alpha-beta by default) and search depth (`depth`, 2 by default), and the
+/// specification of its networks: the feature set (`features`), the hidden layer
+/// sizes as a comma-separated list (`hidden`), the activation function
+/// (`activation`) and the learning rate (`learning-rate`). The options of
+/// `apply_engine_options` may also be given.
+fn new(args: &[String]) {
+    let mut names = vec!["evaluator", "search", "depth", "features", "hidden", "activation", "learning-rate"];
+    names.extend(ENGINE_OPTIONS);
+    let (positional, options) = split_options(args, &names);
+    if positional.is_empty() {
+        println!("Usage: engine new <model file> [{}]", option_usage(&names));
+        return;
+    }
+
+    let mut spec = eval::NetworkSpec::new(parse_option(&options, "features").unwrap_or_default());
+    if let Some(hidden) = options.get("hidden") {
+        let hidden_sizes: Vec<usize> = hidden.split(',').map(|size| size.parse().unwrap()).collect();
+        spec = spec.hidden_sizes(&hidden_sizes);
+    }
+    if let Some(activation) = parse_option(&options, "activation") {
+        spec = spec.activation(activation);
+    }
+    if let Some(learning_rate) = parse_option(&options, "learning-rate") {
+        spec = spec.learning_rate(learning_rate);
+    }
+
+    let search_depth = parse_option(&options, "depth").unwrap_or(2);
+    let search = parse_search(options.get("search").unwrap_or(&"alpha-beta"), search_depth);
+    let evaluator = parse_evaluator(options.get("evaluator").unwrap_or(&"network"), &spec);
+    let mut engine = engine::Engine::with_evaluator(search_depth, search, evaluator);
+    apply_engine_options(&mut engine, &options);
+    engine.save(positional[0]);
+}
+
+/// Calibrate the ProbCut parameters of a saved engine on a file of FEN positions, and
+/// save the result to `args[2]` if given, or back to the model file otherwise. If
+/// `args[3]` is given, it replaces the cut pairs before calibration, as a
+/// comma-separated list of `depth:shallow_depth` pairs such as `3:1,2:0`.
+fn calibrate(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine calibrate <model file> <positions file> [output file] [cut pairs]");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    if let Some(pairs) = args.get(3) {
+        let pairs = pairs.split(',').map(|pair| search::ProbCutPair::from_str(pair).unwrap()).collect();
+        engine.search_mut::<search::ProbCut>().expect("the engine does not use ProbCut").set_pairs(pairs);
+    }
+    let positions = engine::read_positions(&args[1]);
+    engine.calibrate_probcut(&positions);
+    engine.save(args.get(2).unwrap_or(&args[0]));
+}
+
+/// Choose a move in each position of the file `args[1]` with a saved engine that uses
+/// ProbCut, and print how often each cut pair was tried and made a cut. If `args[2]` is
+/// `verify`, each cut is also checked against a full search, and the number of wrong
+/// cuts is printed.
+fn probcut_stats(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine probcut-stats <model file> <positions file> [verify]");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let probcut = engine.search_mut::<search::ProbCut>().expect("the engine does not use ProbCut");
+    probcut.verify = args.get(2).is_some_and(|arg| arg == "verify");
+    for board in engine::read_positions(&args[1]) {
+        engine.best_move(&board, false);
+    }
+    engine.print_search_stats();
+}
+
+/// Fit the mapping from evaluations to win, draw and loss probabilities of a saved engine
+/// to `args[1]` self-play games, and save the result to `args[2]` if given, or back to
+/// the model file otherwise.
+fn calibrate_wdl(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine calibrate-wdl <model file> <number of games> [output file]");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    engine.calibrate_wdl(args[1].parse().unwrap());
+    engine.save(args.get(2).unwrap_or(&args[0]));
+}
+
+/// Read labelled positions from a sample file, labelled with a mixture of their scores
+/// and the results of their games weighted as by `Sample::labelled` with the engine's
+/// WDL model, or from a text file as read by `training::read_labelled_positions` with
+/// the given label mode.
+fn read_training_positions(
+    engine: &engine::Engine,
+    filename: &str,
+    score_weight: f64,
+    mode: training::LabelMode,
+) -> Vec<training::LabelledPosition> {
+    if samples::is_sample_file(filename) {
+        let wdl = engine.wdl();
+        samples::SampleReader::open(filename).map(|sample| sample.labelled(score_weight, wdl)).collect()
+    } else {
+        training::read_labelled_positions(filename, mode)
+    }
+}
+
+/// Play `args[1]` games of self-play with a saved engine on `args[3]` threads (one by
+/// default), and add their positions to the sample file `args[2]`. The options of
+/// `apply_engine_options` apply to these games only.
+fn generate(args: &[String]) {
+    let (args, options) = split_options(args, &ENGINE_OPTIONS);
+    if args.len() < 3 {
+        println!("Usage: engine generate <model file> <games> <samples file> [workers] [{}]", option_usage(&ENGINE_OPTIONS));
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(args[0]);
+    apply_engine_options(&mut engine, &options);
+    let workers = args.get(3).map(|s| s.parse().unwrap()).unwrap_or(1);
+    engine.generate_samples(args[1].parse().unwrap(), workers, args[2]);
+}
+
+/// Label the samples of the file `args[2]` with the scores of searches of depth
+/// `args[1]` by a saved engine on `args[4]` threads (one by default), and write them to
+/// the sample file `args[3]`.
+fn label(args: &[String]) {
+    if args.len() < 4 {
+        println!("Usage: engine label <model file> <depth> <input samples file> <output samples file> [workers]");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let mut samples = samples::read_samples(&args[2]);
+    let workers = args.get(4).map(|s| s.parse().unwrap()).unwrap_or(1);
+    engine.label_samples(&mut samples, args[1].parse().unwrap(), workers);
+
+    let mut writer = samples::SampleWriter::create(&args[3]);
+    for sample in &samples {
+        writer.write(sample);
+    }
+    writer.flush();
+}
+
+/// Train the evaluator of a saved engine on a file of labelled positions or samples
+/// for up to `args[2]` epochs, writing the learning curve to `args[3]`, and save the
+/// result to `args[4]` if given, or back to the model file otherwise. Samples are
+/// labelled with `args[5]` times their scores plus the rest times their results, which
+/// by default are used alone. The numeric labels of a text file are read as given by
+/// `args[6]`: `auto` (the default), `results` or `centipawns`.
+fn train_labelled(args: &[String]) {
+    if args.len() < 4 {
+        println!("Usage: engine train-labelled <model file> <positions file> <epochs> <curve file> [output file] [score weight] [label mode]");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let score_weight = args.get(5).map(|s| s.parse().unwrap()).unwrap_or(0.0);
+    let mode = args.get(6).map(|s| training::LabelMode::from_str(s).unwrap()).unwrap_or_default();
+    let positions = read_training_positions(&engine, &args[1], score_weight, mode);
+    let config = training::TrainingConfig {
+        curve_file: Some(args[3].clone()),
+        ..Default::default()
+    };
+    engine.train_labelled(positions, args[2].parse().unwrap(), &config);
+    engine.save(args.get(4).unwrap_or(&args[0]));
+}
+
+/// Train a saved engine by up to `args[1]` games of self-play, validating on a file of
+/// labelled positions and writing the learning curve to `args[3]`, and save the result
+/// to `args[4]` if given, or back to the model file otherwise.
+fn train_self_play(args: &[String]) {
+    if args.len() < 4 {
+        println!("Usage: engine train-self-play <model file> <games> <validation file> <curve file> [output file]");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let validation = read_training_positions(&engine, &args[2], 0.0, training::LabelMode::Auto);
+    let config = training::TrainingConfig {
+        curve_file: Some(args[3].clone()),
+        ..Default::default()
+    };
+    engine.train_games_validated(args[1].parse().unwrap(), &validation, &config);
+    engine.save(args.get(4).unwrap_or(&args[0]));
+}
+
+/// Train a saved engine by self-play until it has played `args[1]` games in total,
+/// saving a checkpoint to `args[2]` every `args[3]` games (100 by default) and every
+/// `args[4]` minutes if given, and on Ctrl-C. If the checkpoint file exists, training
+/// resumes from it. The options of `apply_engine_options` are applied before training
+/// and saved with the engine.
+fn train(args: &[String]) {
+    let (args, options) = split_options(args, &ENGINE_OPTIONS);
+    if args.len() < 3 {
+        println!(
+            "Usage: engine train <model file> <total games> <checkpoint file> [games per checkpoint] [minutes per checkpoint] [{}]",
+            option_usage(&ENGINE_OPTIONS)
+        );
+        return;
+    }
+
+    let mut engine = if std::path::Path::new(args[2]).exists() {
+        println!("Resuming from {}.", args[2]);
+        engine::Engine::new_from_file(args[2])
+    } else {
+        engine::Engine::new_from_file(args[0])
+    };
+    apply_engine_options(&mut engine, &options);
+    let total_games: u32 = args[1].parse().unwrap();
+    let every_games = args.get(3).map(|s| s.parse().unwrap()).unwrap_or(100);
+    let every_minutes = args.get(4).map(|s| s.parse().unwrap());
+    engine.set_checkpoints(training::Checkpoints::new(args[2], Some(every_games), every_minutes));
+
+    let games_played = engine.progress().games_played;
+    engine.train_games(total_games.saturating_sub(games_played), false);
+    if !training::interrupted() {
+        engine.save(args[0]);
+    }
+}
+
+/// Train a saved engine by self-play for `args[1]` games played on `args[2]` threads,
+/// refreshing the workers' copies of the engine every `args[3]` games (four per worker
+/// by default), and save it to `args[4]`, or back to the model file. The options of
+/// `apply_engine_options` are applied before training and saved with the engine.
+fn train_parallel(args: &[String]) {
+    let (args, options) = split_options(args, &ENGINE_OPTIONS);
+    if args.len() < 3 {
+        println!(
+            "Usage: engine train-parallel <model file> <games> <workers> [games per sync] [output file] [{}]",
+            option_usage(&ENGINE_OPTIONS)
+        );
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(args[0]);
+    apply_engine_options(&mut engine, &options);
+    let workers: usize = args[2].parse().unwrap();
+    let games_per_sync = args.get(3).map(|s| s.parse().unwrap()).unwrap_or(4 * workers as u32);
+    engine.train_games_parallel(args[1].parse().unwrap(), workers, games_per_sync);
+    engine.save(args.get(4).unwrap_or(&args[0]));
+}
+
+/// Convert the network evaluator of a saved engine to a dense network for fast
+/// inference, and save it to `args[1]`.
+fn convert(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine convert <model file> <output file>");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let network = engine.evaluator_mut::<eval::Network>().expect("the engine does not use a network evaluator");
+    dense::DenseNetwork::from_network(network).save(&args[1]);
+}
+
+/// Write an untrained NNUE with `args[1]` hidden neurons to the file `args[0]`.
+fn new_nnue(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine new-nnue <nnue file> <hidden size>");
+        return;
+    }
+
+    nnue::Nnue::new(args[1].parse().unwrap()).save(&args[0]);
+}
+
+/// Replace the evaluator of a saved engine by the NNUE in the file `args[1]`, and save
+/// the result to `args[2]` if given, or back to the model file otherwise.
+fn import_nnue(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine import-nnue <model file> <nnue file> [output file]");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    engine.set_evaluator(Box::new(nnue::Nnue::load(&args[1])));
+    engine.save(args.get(2).unwrap_or(&args[0]));
+}
+
+/// Write the NNUE evaluator of a saved engine to the file `args[1]`.
+fn export_nnue(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine export-nnue <model file> <nnue file>");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let nnue = engine.evaluator_mut::<nnue::Nnue>().expect("the engine does not use an NNUE evaluator");
+    nnue.save(&args[1]);
+}
+
+/// Quantise the network evaluator of a saved engine, and save the result to `args[1]`.
+fn quantise(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine quantise <model file> <output file>");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let network = engine.evaluator_mut::<eval::Network>().expect("the engine does not use a network evaluator");
+    let dense = dense::DenseNetwork::from_network(network);
+    quantised::QuantisedNetwork::from_dense(&dense).save(&args[1]);
+}
+
+/// Report the evaluation error of a quantised network against the network evaluator of
+/// the saved engine it was made from, over a file of FEN positions.
+fn quantisation_error(args: &[String]) {
+    if args.len() < 3 {
+        println!("Usage: engine quantisation-error <model file> <quantised file> <positions file>");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let network = engine.evaluator_mut::<eval::Network>().expect("the engine does not use a network evaluator");
+    let dense = dense::DenseNetwork::from_network(network);
+    let quantised = quantised::QuantisedNetwork::load(&args[1]);
+    let positions = engine::read_positions_with_clocks(&args[2]);
+
+    let error = quantised::quantisation_error(&dense, &quantised, &positions);
+    println!("Positions: {}", error.positions);
+    println!("Mean absolute error: {}", error.mean_absolute);
+    println!("Root mean square error: {}", error.root_mean_square);
+    println!("Maximum absolute error: {}", error.max_absolute);
+}
+
+/// Explain the evaluation of the position given by the FEN string `args[1]` by the
+/// evaluator of a saved engine.
+fn explain(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: engine explain <model file> <FEN>");
+        return;
+    }
+
+    let mut engine = engine::Engine::new_from_file(&args[0]);
+    let board = chess::Board::from_str(&args[1]).expect("invalid FEN");
+    let halfmove_clock = engine::fen_halfmove_clock(&args[1]);
+
+    engine::print_board(&board);
+    let wdl = *engine.wdl();
+    explain::explain(engine.evaluator(), &wdl, &board, halfmove_clock);
+}
+
+/// Compare the mean time per move of engines using the network evaluator, its dense
+/// conversion and the NNUE evaluator, over `args[0]` self-play games (10 by default)
+/// searched to depth `args[1]` (2 by default).
+fn bench_eval(args: &[String]) {
+    let num_games = args.first().map(|s| s.parse().unwrap()).unwrap_or(10);
+    let search_depth = args.get(1).map(|s| s.parse().unwrap()).unwrap_or(2);
+
+    let mut network_engine = engine::Engine::with_evaluator(
+        search_depth,
+        Box::new(search::AlphaBeta),
+        Box::new(eval::Network::new(eval::NetworkSpec::new(features::FeatureSet::OneHot))),
+    );
+    let dense = dense::DenseNetwork::from_network(network_engine.evaluator_mut::<eval::Network>().unwrap());
+    let mut dense_engine = engine::Engine::with_evaluator(search_depth, Box::new(search::AlphaBeta), Box::new(dense));
+    let mut nnue_engine = engine::Engine::with_evaluator(search_depth, Box::new(search::AlphaBeta), Box::new(nnue::Nnue::new(128)));
+
+    let network_nanos = network_engine.measure_mean_nanos_per_move(num_games);
+    let dense_nanos = dense_engine.measure_mean_nanos_per_move(num_games);
+    let nnue_nanos = nnue_engine.measure_mean_nanos_per_move(num_games);
+    println!("Network: {} ns per move", network_nanos);
+    println!("Dense: {} ns per move", dense_nanos);
+    println!("NNUE: {} ns per move", nnue_nanos);
+}
 
 fn main() {
-    let mut engine_1 = engine::Engine::new(0, engine::PruningType::ProbCut);
-    let mut engine_2 = engine::Engine::new(0, engine::PruningType::None);
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|s| s.as_str()) {
+        Some("new") => {
+            new(&args[2..]);
+            return;
+        }
+        Some("calibrate") => {
+            calibrate(&args[2..]);
+            return;
+        }
+        Some("probcut-stats") => {
+            probcut_stats(&args[2..]);
+            return;
+        }
+        Some("calibrate-wdl") => {
+            calibrate_wdl(&args[2..]);
+            return;
+        }
+        Some("train") => {
+            train(&args[2..]);
+            return;
+        }
+        Some("train-parallel") => {
+            train_parallel(&args[2..]);
+            return;
+        }
+        Some("generate") => {
+            generate(&args[2..]);
+            return;
+        }
+        Some("label") => {
+            label(&args[2..]);
+            return;
+        }
+        Some("train-labelled") => {
+            train_labelled(&args[2..]);
+            return;
+        }
+        Some("train-self-play") => {
+            train_self_play(&args[2..]);
+            return;
+        }
+        Some("convert") => {
+            convert(&args[2..]);
+            return;
+        }
+        Some("new-nnue") => {
+            new_nnue(&args[2..]);
+            return;
+        }
+        Some("import-nnue") => {
+            import_nnue(&args[2..]);
+            return;
+        }
+        Some("export-nnue") => {
+            export_nnue(&args[2..]);
+            return;
+        }
+        Some("quantise") => {
+            quantise(&args[2..]);
+            return;
+        }
+        Some("quantisation-error") => {
+            quantisation_error(&args[2..]);
+            return;
+        }
+        Some("explain") => {
+            explain(&args[2..]);
+            return;
+        }
+        Some("bench-eval") => {
+            bench_eval(&args[2..]);
+            return;
+        }
+        _ => {}
+    }
+
+    let mut engine_1 = engine::Engine::new(0, Box::new(search::ProbCut::for_depth(0)), eval::NetworkSpec::default());
+    let mut engine_2 = engine::Engine::new(0, Box::new(search::Unpruned), eval::NetworkSpec::default());
 
     //engine_1.train_secs(60, false);
 
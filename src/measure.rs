@@ -12,8 +12,8 @@ pub fn measure_elo(engine_1: &mut Engine, engine_2: &mut Engine, num_games: u32)
 
     let pb = ProgressBar::new(num_games as u64);
     for i in 0..num_games {
-        let expected_1 = 1.0 / (1.0 + 10f32.powf((rating_2 - rating_1) as f32 / 400.0));
-        let expected_2 = 1.0 / (1.0 + 10f32.powf((rating_1 - rating_2) as f32 / 400.0));
+        let expected_1 = 1.0 / (1.0 + 10f32.powf((rating_2 - rating_1) / 400.0));
+        let expected_2 = 1.0 / (1.0 + 10f32.powf((rating_1 - rating_2) / 400.0));
 
         if i % 2 == 0 {
             let result = play_engines(engine_1, engine_2, false);
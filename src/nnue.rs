@@ -0,0 +1,346 @@
+#![allow(dead_code)]
+
+use crate::eval::Evaluator;
+use crate::features::flip_square;
+use chess::{Board, Color, Piece, Square, ALL_COLORS, ALL_PIECES};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::io::{Read, Write};
+
+/// The number of inputs: one for each piece of each colour on each square.
+pub const NUM_INPUTS: usize = 12 * 64;
+
+const MAGIC: &[u8; 4] = b"NNUE";
+const FILE_VERSION: u32 = 2;
+
+/// Return the index of the input for a piece of a given colour on a given square, as
+/// seen by `perspective`. Black sees the board mirrored, with the colours swapped, so
+/// its own pieces use the same inputs as White's own pieces.
+fn input_index(perspective: Color, color: Color, piece: Piece, square: Square) -> usize {
+    if perspective == Color::White {
+        (color.to_index() * 6 + piece.to_index()) * 64 + square.to_index()
+    } else {
+        ((!color).to_index() * 6 + piece.to_index()) * 64 + flip_square(square).to_index()
+    }
+}
+
+/// Return the indices of the inputs that are on for a given board, as seen by
+/// `perspective`.
+fn active_inputs(board: &Board, perspective: Color) -> Vec<usize> {
+    let mut inputs = Vec::with_capacity(32);
+    for color in ALL_COLORS {
+        for piece in ALL_PIECES {
+            for square in board.pieces(piece) & board.color_combined(color) {
+                inputs.push(input_index(perspective, color, piece, square));
+            }
+        }
+    }
+
+    inputs
+}
+
+/// Add `sign` times the weights of input `index` to an accumulator, where the input
+/// weights are stored in rows of `accumulator.len()` weights.
+fn update_accumulator(input_weights: &[f32], accumulator: &mut [f32], index: usize, sign: f32) {
+    let hidden_size = accumulator.len();
+    let weights = &input_weights[index * hidden_size..(index + 1) * hidden_size];
+    for (a, w) in accumulator.iter_mut().zip(weights) {
+        *a += sign * w;
+    }
+}
+
+/// The clipped ReLU activation of the hidden layer.
+fn crelu(x: f32) -> f32 {
+    x.clamp(0.0, 1.0)
+}
+
+/// An efficiently updatable neural network. The hidden layer takes one-hot piece-square
+/// inputs, so its pre-activations (the accumulator) can be updated by adding and
+/// subtracting the weights of the few inputs a move changes, instead of being
+/// recomputed at every leaf of the search. An accumulator is kept for each side's
+/// perspective, and the output is computed from that of the side to move. The
+/// accumulators after each move made are kept on a stack that is allocated once and
+/// reused, so making a move only copies and updates them in place.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Nnue {
+    hidden_size: usize,
+    input_weights: Vec<f32>, // NUM_INPUTS rows of hidden_size weights
+    hidden_biases: Vec<f32>,
+    output_weights: Vec<f32>,
+    output_bias: f32,
+    learning_rate: f32,
+    #[serde(skip)]
+    stack: Vec<f32>, // the White and Black accumulators of each entry, 2 * hidden_size per entry
+    #[serde(skip)]
+    hashes: Vec<u64>, // the board hash of each live entry of the stack
+}
+
+impl Nnue {
+    /// Create an untrained network with the given number of hidden neurons.
+    pub fn new(hidden_size: usize) -> Nnue {
+        let mut rng = rand::thread_rng();
+        let input_range = 1.0 / (32.0f32).sqrt(); // about 32 inputs are on at once
+        let output_range = 1.0 / (hidden_size as f32).sqrt();
+
+        Nnue {
+            hidden_size,
+            input_weights: (0..NUM_INPUTS * hidden_size)
+                .map(|_| rng.gen_range(-input_range..input_range))
+                .collect(),
+            hidden_biases: vec![0.0; hidden_size],
+            output_weights: (0..hidden_size)
+                .map(|_| rng.gen_range(-output_range..output_range))
+                .collect(),
+            output_bias: 0.0,
+            learning_rate: 0.01,
+            stack: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Set the learning rate used by `fit`.
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.learning_rate = learning_rate;
+    }
+
+    /// Return the accumulator of a board as seen by `perspective`, computed from
+    /// scratch.
+    fn refresh(&self, board: &Board, perspective: Color) -> Vec<f32> {
+        let mut accumulator = self.hidden_biases.clone();
+        for index in active_inputs(board, perspective) {
+            update_accumulator(&self.input_weights, &mut accumulator, index, 1.0);
+        }
+
+        accumulator
+    }
+
+    /// Return the output of the network from the accumulator of the side to move.
+    fn output(&self, accumulator: &[f32]) -> f32 {
+        let hidden: f32 = accumulator
+            .iter()
+            .zip(&self.output_weights)
+            .map(|(a, w)| crelu(*a) * w)
+            .sum();
+
+        hidden + self.output_bias
+    }
+
+    /// Return the accumulator of entry `entry` of the stack as seen by `perspective`.
+    fn accumulator(&self, entry: usize, perspective: Color) -> &[f32] {
+        let start = (2 * entry + perspective.to_index()) * self.hidden_size;
+        &self.stack[start..start + self.hidden_size]
+    }
+
+    /// Make sure the top of the accumulator stack belongs to `board`, recomputing it if
+    /// the search has moved somewhere the stack does not know about.
+    fn sync(&mut self, board: &Board) {
+        let hash = board.get_hash();
+        if self.hashes.last() != Some(&hash) {
+            let size = self.hidden_size;
+            if self.stack.len() < 2 * size {
+                self.stack.resize(2 * size, 0.0);
+            }
+            for perspective in ALL_COLORS {
+                let accumulator = self.refresh(board, perspective);
+                let start = perspective.to_index() * size;
+                self.stack[start..start + size].copy_from_slice(&accumulator);
+            }
+            self.hashes.clear();
+            self.hashes.push(hash);
+        }
+    }
+
+    /// Save the network to the given file in its binary format: the magic bytes
+    /// `NNUE`, the format version, the hidden layer size and the learning rate,
+    /// followed by every weight as a little-endian `f32`.
+    pub fn save(&self, filename: &str) {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FILE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.learning_rate.to_le_bytes());
+
+        let weights = self
+            .input_weights
+            .iter()
+            .chain(&self.hidden_biases)
+            .chain(&self.output_weights)
+            .chain([&self.output_bias]);
+        for weight in weights {
+            bytes.extend_from_slice(&weight.to_le_bytes());
+        }
+
+        let mut file = std::fs::File::create(filename).unwrap();
+        file.write_all(&bytes).unwrap();
+    }
+
+    /// Load a network saved by `save`.
+    pub fn load(filename: &str) -> Nnue {
+        let mut file = std::fs::File::open(filename).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+
+        assert!(&bytes[0..4] == MAGIC, "not an NNUE file");
+        let mut words = bytes[4..].chunks_exact(4).map(|word| [word[0], word[1], word[2], word[3]]);
+        let version = u32::from_le_bytes(words.next().unwrap());
+        assert!(version == FILE_VERSION, "unsupported NNUE file version {}", version);
+        let hidden_size = u32::from_le_bytes(words.next().unwrap()) as usize;
+        let learning_rate = f32::from_le_bytes(words.next().unwrap());
+
+        let mut weights = words.map(f32::from_le_bytes);
+        let mut take = |n: usize| -> Vec<f32> { weights.by_ref().take(n).collect() };
+        let input_weights = take(NUM_INPUTS * hidden_size);
+        let hidden_biases = take(hidden_size);
+        let output_weights = take(hidden_size);
+        let output_bias = take(1);
+        assert!(output_bias.len() == 1, "truncated NNUE file");
+
+        Nnue {
+            hidden_size,
+            input_weights,
+            hidden_biases,
+            output_weights,
+            output_bias: output_bias[0],
+            learning_rate,
+            stack: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+}
+
+#[typetag::serde]
+impl Evaluator for Nnue {
+    fn evaluate(&mut self, board: &Board, _halfmove_clock: u16) -> f64 {
+        self.sync(board);
+
+        self.output(self.accumulator(self.hashes.len() - 1, board.side_to_move())) as f64
+    }
+
+    fn uses_halfmove_clock(&self) -> bool {
+        false
+    }
+
+    fn make_move(&mut self, board: &Board, new_board: &Board) {
+        self.sync(board);
+        let size = self.hidden_size;
+        let top = self.hashes.len() - 1;
+        if self.stack.len() < 2 * (top + 2) * size {
+            self.stack.resize(2 * (top + 2) * size, 0.0);
+        }
+        self.stack.copy_within(2 * top * size..2 * (top + 1) * size, 2 * (top + 1) * size);
+
+        let entry = &mut self.stack[2 * (top + 1) * size..2 * (top + 2) * size];
+        for color in ALL_COLORS {
+            for piece in ALL_PIECES {
+                let before = board.pieces(piece) & board.color_combined(color);
+                let after = new_board.pieces(piece) & new_board.color_combined(color);
+                for perspective in ALL_COLORS {
+                    let accumulator = &mut entry[perspective.to_index() * size..(perspective.to_index() + 1) * size];
+                    for square in before & !after {
+                        update_accumulator(&self.input_weights, accumulator, input_index(perspective, color, piece, square), -1.0);
+                    }
+                    for square in after & !before {
+                        update_accumulator(&self.input_weights, accumulator, input_index(perspective, color, piece, square), 1.0);
+                    }
+                }
+            }
+        }
+
+        self.hashes.push(new_board.get_hash());
+    }
+
+    fn unmake_move(&mut self) {
+        self.hashes.pop();
+    }
+
+    /// Take one step of gradient descent on the squared error between the output and
+    /// `target`.
+    fn fit(&mut self, board: &Board, halfmove_clock: u16, target: f64) {
+        self.fit_batch(&[(*board, halfmove_clock, target)]);
+    }
+
+    /// Take one step of gradient descent on the mean squared error over the batch, with
+    /// every gradient computed from the weights before the step.
+    fn fit_batch(&mut self, samples: &[(Board, u16, f64)]) {
+        let forward: Vec<(Vec<usize>, Vec<f32>, f32)> = samples
+            .iter()
+            .map(|(board, _, target)| {
+                let accumulator = self.refresh(board, board.side_to_move());
+                let error = self.output(&accumulator) - *target as f32;
+                (active_inputs(board, board.side_to_move()), accumulator, error)
+            })
+            .collect();
+        let output_weights = self.output_weights.clone();
+
+        for (inputs, accumulator, error) in forward {
+            let step = self.learning_rate * error / samples.len() as f32;
+            for (j, &a) in accumulator.iter().enumerate() {
+                let hidden_step = if a > 0.0 && a < 1.0 {
+                    step * output_weights[j]
+                } else {
+                    0.0
+                };
+
+                self.output_weights[j] -= step * crelu(a);
+                self.hidden_biases[j] -= hidden_step;
+                for &index in &inputs {
+                    self.input_weights[index * self.hidden_size + j] -= hidden_step;
+                }
+            }
+            self.output_bias -= step;
+        }
+
+        // the weights have changed, so every stored accumulator is stale
+        self.hashes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::tests::mirrored_pairs;
+    use chess::MoveGen;
+
+    #[test]
+    fn nnue_is_colour_symmetric() {
+        let mut nnue = Nnue::new(16);
+        for (board, mirrored) in mirrored_pairs() {
+            let value = nnue.evaluate(&board, 0);
+            let mirrored_value = nnue.evaluate(&mirrored, 0);
+            assert!((value - mirrored_value).abs() < 1e-5, "{} != {} for {}", value, mirrored_value, board);
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_refresh() {
+        let mut nnue = Nnue::new(16);
+        for (board, _) in mirrored_pairs() {
+            for m in MoveGen::new_legal(&board) {
+                let new_board = board.make_move_new(m);
+                nnue.evaluate(&board, 0);
+                nnue.make_move(&board, &new_board);
+                let incremental = nnue.evaluate(&new_board, 0);
+                nnue.unmake_move();
+
+                let mut fresh = nnue.clone();
+                fresh.hashes.clear();
+                let refreshed = fresh.evaluate(&new_board, 0);
+                assert!((incremental - refreshed).abs() < 1e-4, "{} != {} after {}", incremental, refreshed, m);
+
+                // and one move deeper, so that the stack grows past its first entries
+                if let Some(reply) = MoveGen::new_legal(&new_board).next() {
+                    let reply_board = new_board.make_move_new(reply);
+                    nnue.evaluate(&board, 0);
+                    nnue.make_move(&board, &new_board);
+                    nnue.make_move(&new_board, &reply_board);
+                    let incremental = nnue.evaluate(&reply_board, 0);
+                    nnue.unmake_move();
+                    nnue.unmake_move();
+
+                    let refreshed = fresh.evaluate(&reply_board, 0);
+                    assert!((incremental - refreshed).abs() < 1e-4, "{} != {} after {} {}", incremental, refreshed, m, reply);
+                }
+            }
+        }
+    }
+}
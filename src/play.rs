@@ -1,6 +1,9 @@
 #![allow(dead_code)]
 
 use crate::engine::{Engine, print_board};
+use crate::eval::side_sign;
+use crate::explain;
+use crate::history::History;
 use chess::{Board, BoardStatus, Game, GameResult, ChessMove, Color};
 use std::io::{self, Write};
 use text_io::read;
@@ -14,9 +17,29 @@ fn is_valid_move(board: &Board, player_move_str: &str) -> bool {
     }
 }
 
-/// Play a game of chess as a given colour against a given engine.
+/// Print the engine's evaluation and reply if the player were to play a given move,
+/// without playing it.
+fn what_if(engine: &mut Engine, board: &Board, history: &History, player_move: ChessMove) {
+    let new_board = board.make_move_new(player_move);
+    let mut new_history = history.clone();
+    new_history.push(board, player_move, &new_board);
+
+    println!("If {}:", player_move);
+    print_board(&new_board);
+    if new_board.status() == BoardStatus::Ongoing {
+        let reply = engine.best_move_with_history(&new_board, &new_history, false);
+        println!("Engine reply: {}", reply);
+    }
+    let value = engine.evaluate_board_with_history(&new_board, &new_history) * side_sign(&new_board);
+    println!("Eval: {} for White\n", engine.wdl().describe(value));
+}
+
+/// Play a game of chess as a given colour against a given engine. Entering
+/// `whatif <move>` instead of a move shows what would happen after that move, and
+/// entering `explain` explains the engine's evaluation of the current position.
 pub fn play_game(player_color: Color, engine: &mut Engine) {
     let mut board = Board::default();
+    let mut history = History::new(&board);
     print_board(&board);
 
     // main game loop
@@ -27,10 +50,27 @@ pub fn play_game(player_color: Color, engine: &mut Engine) {
                 let _ = io::stdout().flush();
                 let player_move_str: String = read!("{}\n");
 
+                if player_move_str == "explain" {
+                    let wdl = *engine.wdl();
+                    explain::explain(engine.evaluator(), &wdl, &board, history.halfmove_clock());
+                    continue;
+                }
+
+                if let Some(what_if_str) = player_move_str.strip_prefix("whatif ") {
+                    if is_valid_move(&board, what_if_str) {
+                        let what_if_move = ChessMove::from_san(&board, what_if_str).unwrap();
+                        what_if(engine, &board, &history, what_if_move);
+                    } else {
+                        println!("Invalid move.");
+                    }
+                    continue;
+                }
+
                 if is_valid_move(&board, &player_move_str) {
-                    let mut temp_board = board.clone();
+                    let mut temp_board = board;
                     let player_move = ChessMove::from_san(&board, &player_move_str).unwrap();
                     board.make_move(player_move, &mut temp_board);
+                    history.push(&board, player_move, &temp_board);
                     board = temp_board;
                     break;
                 } else {
@@ -39,15 +79,17 @@ pub fn play_game(player_color: Color, engine: &mut Engine) {
             }
             println!();
         } else {
-            let best_move = engine.best_move(&board, false);
-            let mut temp_board = board.clone();
+            let best_move = engine.best_move_with_history(&board, &history, false);
+            let mut temp_board = board;
             board.make_move(best_move, &mut temp_board);
+            history.push(&board, best_move, &temp_board);
             board = temp_board;
             println!("Engine move: {}\n", best_move);
         }
 
         print_board(&board);
-        println!("Eval: {}\n", engine.evaluate_board(&board));
+        let value = engine.evaluate_board_with_history(&board, &history) * side_sign(&board);
+        println!("Eval: {} for White\n", engine.wdl().describe(value));
     }
 }
 
@@ -55,6 +97,7 @@ pub fn play_game(player_color: Color, engine: &mut Engine) {
 /// wins, -1 if black wins, and 0 if it's a draw.
 pub fn play_engines(engine_white: &mut Engine, engine_black: &mut Engine, show: bool) -> i8 {
     let mut game = Game::new();
+    let mut history = History::new(&game.current_position());
     if show {
         print_board(&game.current_position());
     }
@@ -63,19 +106,24 @@ pub fn play_engines(engine_white: &mut Engine, engine_black: &mut Engine, show:
     while game.result().is_none() {
         if game.can_declare_draw() {
             game.declare_draw();
+            break;
         }
 
         if game.side_to_move() == Color::White {
-            let best_move = engine_white.best_move(&game.current_position(), false);
+            let board = game.current_position();
+            let best_move = engine_white.best_move_with_history(&board, &history, false);
             game.make_move(best_move);
+            history.push(&board, best_move, &game.current_position());
 
             if show {
                 println!("White: {}\n", best_move);
                 print_board(&game.current_position());
             }
         } else {
-            let best_move = engine_black.best_move(&game.current_position(), false);
+            let board = game.current_position();
+            let best_move = engine_black.best_move_with_history(&board, &history, false);
             game.make_move(best_move);
+            history.push(&board, best_move, &game.current_position());
 
             if show {
                 println!("Black: {}\n", best_move);
@@ -0,0 +1,180 @@
+#![allow(dead_code)]
+
+use crate::dense::{board_inputs, is_activated, side_to_move, DenseNetwork, NetworkFile};
+use crate::eval::{Activation, Evaluator, Perspective};
+use crate::features::FeatureSet;
+use chess::Board;
+use serde::{Serialize, Deserialize};
+
+/// The scale of the `i16` inputs of every layer: a value of 1.0 is stored as 1024. This
+/// covers the largest compact feature (6.0) and every hidden activation, which lie in
+/// [-1, 1], with about three decimal places of precision.
+const ACTIVATION_SCALE: f32 = 1024.0;
+
+/// A fully connected layer with `i8` weights and `i32` biases. A weight `w` is stored
+/// as `w / weight_scale`, where the scale maps the largest weight of the layer to 127.
+#[derive(Serialize, Deserialize, Clone)]
+struct QuantisedLayer {
+    inputs: usize,
+    outputs: usize,
+    weight_scale: f32,
+    weights: Vec<i8>,
+    biases: Vec<i32>,
+}
+
+impl QuantisedLayer {
+    /// Return the weighted sums of the inputs for each output neuron, as floats.
+    fn sums(&self, inputs: &[i16]) -> Vec<f32> {
+        (0..self.outputs)
+            .map(|output| {
+                let row = &self.weights[output * self.inputs..(output + 1) * self.inputs];
+                let sum = self.biases[output]
+                    + row.iter().zip(inputs).map(|(&w, &x)| w as i32 * x as i32).sum::<i32>();
+
+                sum as f32 * self.weight_scale / ACTIVATION_SCALE
+            })
+            .collect()
+    }
+}
+
+/// Quantise a value to an `i16` input of a layer.
+fn quantise_activation(x: f32) -> i16 {
+    (x * ACTIVATION_SCALE).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// A quantised copy of a dense network, with `i16` activations and `i8` weights
+/// multiplied and summed in integer arithmetic. Activation functions are still
+/// computed in floating point, on the sums of each layer.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QuantisedNetwork {
+    feature_set: FeatureSet,
+    activation: Activation,
+    #[serde(default = "side_to_move")]
+    perspective: Perspective,
+    layers: Vec<QuantisedLayer>,
+}
+
+impl QuantisedNetwork {
+    /// Quantise a dense network.
+    pub fn from_dense(network: &DenseNetwork) -> QuantisedNetwork {
+        let layers = network
+            .layers()
+            .iter()
+            .map(|layer| {
+                let largest = layer.weights.iter().fold(0.0f32, |max, w| max.max(w.abs()));
+                let weight_scale = if largest > 0.0 { largest / 127.0 } else { 1.0 };
+
+                QuantisedLayer {
+                    inputs: layer.inputs,
+                    outputs: layer.outputs,
+                    weight_scale,
+                    weights: layer.weights.iter().map(|w| (w / weight_scale).round() as i8).collect(),
+                    biases: layer
+                        .biases
+                        .iter()
+                        .map(|b| (b * ACTIVATION_SCALE / weight_scale).round() as i32)
+                        .collect(),
+                }
+            })
+            .collect();
+
+        QuantisedNetwork {
+            feature_set: network.feature_set(),
+            activation: network.activation(),
+            perspective: network.perspective(),
+            layers,
+        }
+    }
+
+    /// Return the output of the network for the given features.
+    pub fn calc(&self, features: &[f32]) -> f32 {
+        let mut inputs: Vec<i16> = features.iter().map(|&x| quantise_activation(x)).collect();
+        let mut outputs = Vec::new();
+        for (i, layer) in self.layers.iter().enumerate() {
+            outputs = layer.sums(&inputs);
+            if is_activated(i, self.layers.len()) {
+                outputs = outputs.iter().map(|&sum| self.activation.apply(sum)).collect();
+            }
+            inputs = outputs.iter().map(|&x| quantise_activation(x)).collect();
+        }
+
+        outputs[0]
+    }
+
+    /// Return the evaluation of `board`, positive when the side to move is better.
+    pub fn evaluate_board(&self, board: &Board, halfmove_clock: u16) -> f32 {
+        let (features, sign) = board_inputs(self.feature_set, self.perspective, board, halfmove_clock);
+        self.calc(&features) * sign
+    }
+}
+
+impl NetworkFile for QuantisedNetwork {}
+
+#[typetag::serde]
+impl Evaluator for QuantisedNetwork {
+    fn evaluate(&mut self, board: &Board, halfmove_clock: u16) -> f64 {
+        self.evaluate_board(board, halfmove_clock) as f64
+    }
+
+    fn uses_halfmove_clock(&self) -> bool {
+        self.feature_set.uses_halfmove_clock()
+    }
+}
+
+/// The error of a quantised network against the network it was made from.
+pub struct QuantisationError {
+    pub positions: usize,
+    pub mean_absolute: f32,
+    pub root_mean_square: f32,
+    pub max_absolute: f32,
+}
+
+/// Measure the error introduced by quantising `dense` to `quantised` over a set of
+/// positions, each given with its halfmove clock.
+pub fn quantisation_error(dense: &DenseNetwork, quantised: &QuantisedNetwork, positions: &[(Board, u16)]) -> QuantisationError {
+    let errors: Vec<f32> = positions
+        .iter()
+        .map(|&(board, halfmove_clock)| {
+            (dense.evaluate_board(&board, halfmove_clock) - quantised.evaluate_board(&board, halfmove_clock)).abs()
+        })
+        .collect();
+    let n = errors.len().max(1) as f32;
+
+    QuantisationError {
+        positions: errors.len(),
+        mean_absolute: errors.iter().sum::<f32>() / n,
+        root_mean_square: (errors.iter().map(|e| e * e).sum::<f32>() / n).sqrt(),
+        max_absolute: errors.iter().fold(0.0, |max, e| max.max(*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{Network, NetworkSpec};
+    use crate::features::tests::mirrored_pairs;
+
+    // the networks are untrained, with random weights, so the bounds leave room for
+    // unlucky draws
+    #[test]
+    fn quantisation_error_is_small() {
+        let positions: Vec<(Board, u16)> = mirrored_pairs()
+            .into_iter()
+            .flat_map(|(board, mirrored)| [board, mirrored])
+            .enumerate()
+            .map(|(i, board)| (board, 7 * i as u16))
+            .collect();
+
+        for feature_set in [FeatureSet::Compact, FeatureSet::OneHot, FeatureSet::Extended] {
+            for activation in [Activation::Tanh, Activation::Sigmoid] {
+                let spec = NetworkSpec::new(feature_set).hidden_sizes(&[32, 8]).activation(activation);
+                let dense = DenseNetwork::from_network(&Network::new(spec.clone()));
+                let quantised = QuantisedNetwork::from_dense(&dense);
+
+                let error = quantisation_error(&dense, &quantised, &positions);
+                assert!(error.mean_absolute < 0.06, "mean absolute error {} with {:?}", error.mean_absolute, spec);
+                assert!(error.max_absolute < 0.2, "maximum absolute error {} with {:?}", error.max_absolute, spec);
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+#![allow(dead_code)]
+
+use crate::eval::side_sign;
+use crate::training::LabelledPosition;
+use crate::wdl::WdlModel;
+use chess::{BitBoard, Board, BoardBuilder, BoardStatus, CastleRights, Color, File, ALL_PIECES};
+use std::fs::{File as FsFile, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+/// The bytes at the start of every sample file, the last of which is the version of
+/// the format.
+const MAGIC: [u8; 4] = *b"SMP\x01";
+
+/// The size of a packed sample in bytes: the occupied squares (8), a nibble for the
+/// piece on each of up to 32 of them (16), castling rights and side to move (1), the
+/// en passant file (1), the halfmove clock (2), the score (4), the result (1) and the
+/// ply (2), with numbers in little-endian order.
+pub const SAMPLE_SIZE: usize = 35;
+
+/// A training sample: a position from a self-play game, with the value of the search
+/// from it and the result of the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub board: Board,
+    pub halfmove_clock: u16,
+    /// The value of the search from the position, for the side to move. The last
+    /// position of a game is scored with its result.
+    pub score: f32,
+    /// The result of the game: 1 if White won, -1 if Black won and 0 for a draw.
+    pub result: i8,
+    /// The number of plies played before the position.
+    pub ply: u16,
+}
+
+impl Sample {
+    /// Return the samples of a self-play game, given as by `Engine::play_self`.
+    pub fn from_game(winner: i8, positions: &[(Board, u16)], search_values: &[f64]) -> Vec<Sample> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(ply, (board, halfmove_clock))| Sample {
+                board: *board,
+                halfmove_clock: *halfmove_clock,
+                score: search_values.get(ply).copied().unwrap_or(winner as f64 * side_sign(board)) as f32,
+                result: winner,
+                ply: ply as u16,
+            })
+            .collect()
+    }
+
+    /// Return the position labelled with a mixture of its score and the result of its
+    /// game: `score_weight` times the score plus `1 - score_weight` times the result,
+    /// so a weight of zero trains on results alone and a weight of one on scores alone.
+    /// The score is first mapped by `wdl` to the result it predicts, from -1 for a loss
+    /// to 1 for a win, so that it is mixed on the same scale as the result. A checkmate,
+    /// which ends a decisive game, is scored with the result itself. The last position
+    /// of a drawn game is scored zero, which the mapping keeps.
+    pub fn labelled(&self, score_weight: f64, wdl: &WdlModel) -> LabelledPosition {
+        let score = if self.board.status() == BoardStatus::Checkmate {
+            self.result as f64
+        } else {
+            (2.0 * wdl.expected_score(self.score as f64) - 1.0) * side_sign(&self.board)
+        };
+        LabelledPosition {
+            board: self.board,
+            halfmove_clock: self.halfmove_clock,
+            target: score_weight * score + (1.0 - score_weight) * self.result as f64,
+        }
+    }
+
+    /// Pack the sample into bytes. Pieces are numbered from 0 to 5 for White and 6 to
+    /// 11 for Black, in the order of the occupied squares.
+    pub fn pack(&self) -> [u8; SAMPLE_SIZE] {
+        let mut bytes = [0; SAMPLE_SIZE];
+        let occupied = *self.board.combined();
+        bytes[0..8].copy_from_slice(&occupied.0.to_le_bytes());
+        for (i, square) in occupied.enumerate() {
+            let piece = self.board.piece_on(square).unwrap().to_index();
+            let color = self.board.color_on(square).unwrap().to_index();
+            bytes[8 + i / 2] |= ((6 * color + piece) as u8) << (4 * (i % 2));
+        }
+
+        bytes[24] = (self.board.side_to_move() == Color::Black) as u8
+            | (self.board.castle_rights(Color::White).to_index() as u8) << 1
+            | (self.board.castle_rights(Color::Black).to_index() as u8) << 3;
+        bytes[25] = match self.board.en_passant() {
+            Some(square) => square.get_file().to_index() as u8,
+            None => u8::MAX,
+        };
+        bytes[26..28].copy_from_slice(&self.halfmove_clock.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.score.to_le_bytes());
+        bytes[32] = self.result as u8;
+        bytes[33..35].copy_from_slice(&self.ply.to_le_bytes());
+
+        bytes
+    }
+
+    /// Unpack a sample packed by `pack`.
+    pub fn unpack(bytes: &[u8; SAMPLE_SIZE]) -> Sample {
+        let mut builder = BoardBuilder::new();
+        let occupied = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        for (i, square) in BitBoard(occupied).enumerate() {
+            let code = (bytes[8 + i / 2] >> (4 * (i % 2))) as usize & 0xf;
+            let color = if code < 6 { Color::White } else { Color::Black };
+            builder.piece(square, ALL_PIECES[code % 6], color);
+        }
+
+        let flags = bytes[24] as usize;
+        builder.side_to_move(if flags & 1 == 0 { Color::White } else { Color::Black });
+        builder.castle_rights(Color::White, CastleRights::from_index((flags >> 1) & 3));
+        builder.castle_rights(Color::Black, CastleRights::from_index((flags >> 3) & 3));
+        if bytes[25] != u8::MAX {
+            builder.en_passant(Some(File::from_index(bytes[25] as usize)));
+        }
+
+        Sample {
+            board: Board::try_from(&builder).unwrap(),
+            halfmove_clock: u16::from_le_bytes([bytes[26], bytes[27]]),
+            score: f32::from_le_bytes(bytes[28..32].try_into().unwrap()),
+            result: bytes[32] as i8,
+            ply: u16::from_le_bytes([bytes[33], bytes[34]]),
+        }
+    }
+}
+
+/// Return true if the given file starts like a sample file.
+pub fn is_sample_file(filename: &str) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    match FsFile::open(filename) {
+        Ok(mut file) => file.read_exact(&mut magic).is_ok() && magic == MAGIC,
+        Err(_) => false,
+    }
+}
+
+/// Writes samples to a file.
+pub struct SampleWriter {
+    writer: BufWriter<FsFile>,
+}
+
+impl SampleWriter {
+    /// Create a new sample file, replacing any existing file.
+    pub fn create(filename: &str) -> SampleWriter {
+        let mut writer = BufWriter::new(FsFile::create(filename).unwrap());
+        writer.write_all(&MAGIC).unwrap();
+
+        SampleWriter { writer }
+    }
+
+    /// Open a sample file to add samples to the end of it, creating it if it does not
+    /// exist.
+    pub fn append(filename: &str) -> SampleWriter {
+        if !std::path::Path::new(filename).exists() {
+            return SampleWriter::create(filename);
+        }
+        assert!(is_sample_file(filename), "not a sample file: {}", filename);
+        let file = OpenOptions::new().append(true).open(filename).unwrap();
+
+        SampleWriter { writer: BufWriter::new(file) }
+    }
+
+    /// Write a sample.
+    pub fn write(&mut self, sample: &Sample) {
+        self.writer.write_all(&sample.pack()).unwrap();
+    }
+
+    /// Write the samples of a self-play game, given as by `Engine::play_self`.
+    pub fn write_game(&mut self, winner: i8, positions: &[(Board, u16)], search_values: &[f64]) {
+        for sample in Sample::from_game(winner, positions, search_values) {
+            self.write(&sample);
+        }
+    }
+
+    /// Write any buffered samples to the file.
+    pub fn flush(&mut self) {
+        self.writer.flush().unwrap();
+    }
+}
+
+/// Reads the samples of a file in order.
+pub struct SampleReader {
+    reader: BufReader<FsFile>,
+}
+
+impl SampleReader {
+    /// Open a sample file.
+    pub fn open(filename: &str) -> SampleReader {
+        assert!(is_sample_file(filename), "not a sample file: {}", filename);
+        let mut reader = BufReader::new(FsFile::open(filename).unwrap());
+        reader.read_exact(&mut [0; MAGIC.len()]).unwrap();
+
+        SampleReader { reader }
+    }
+}
+
+impl Iterator for SampleReader {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        let mut bytes = [0; SAMPLE_SIZE];
+        match self.reader.read_exact(&mut bytes) {
+            Ok(()) => Some(Sample::unpack(&bytes)),
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => None,
+            Err(error) => panic!("{}", error),
+        }
+    }
+}
+
+/// Read every sample of a sample file.
+pub fn read_samples(filename: &str) -> Vec<Sample> {
+    SampleReader::open(filename).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::tests::POSITIONS;
+    use std::str::FromStr;
+
+    #[test]
+    fn samples_survive_packing() {
+        for (i, fen) in POSITIONS.iter().enumerate() {
+            let sample = Sample {
+                board: Board::from_str(fen).unwrap(),
+                halfmove_clock: i as u16 * 7,
+                score: -0.25 * i as f32,
+                result: i as i8 % 3 - 1,
+                ply: 300 + i as u16,
+            };
+            assert_eq!(Sample::unpack(&sample.pack()), sample, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn scores_are_labelled_as_expected_results() {
+        let wdl = WdlModel::default();
+        for fen in POSITIONS {
+            let board = Board::from_str(fen).unwrap();
+            let sample = Sample { board, halfmove_clock: 0, score: 0.5, result: 1, ply: 0 };
+            let expected = (2.0 * wdl.expected_score(0.5) - 1.0) * side_sign(&board);
+
+            assert!((sample.labelled(1.0, &wdl).target - expected).abs() < 1e-9, "{}", fen);
+            assert_eq!(sample.labelled(0.0, &wdl).target, 1.0, "{}", fen);
+            let mixed = sample.labelled(0.25, &wdl).target;
+            assert!((mixed - (0.25 * expected + 0.75)).abs() < 1e-9, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn game_ends_are_labelled_with_results() {
+        let wdl = WdlModel::default();
+        let checkmate = Board::from_str("3R2k1/5ppp/8/8/8/8/5PPP/6K1 b - - 1 1").unwrap();
+        let samples = Sample::from_game(1, &[(checkmate, 1)], &[]);
+        assert_eq!(samples[0].labelled(1.0, &wdl).target, 1.0);
+
+        let drawn = Board::from_str(POSITIONS[0]).unwrap();
+        let samples = Sample::from_game(0, &[(drawn, 1)], &[]);
+        assert_eq!(samples[0].labelled(1.0, &wdl).target, 0.0);
+    }
+}
@@ -0,0 +1,521 @@
+#![allow(dead_code)]
+
+use crate::cache::EvalCache;
+use crate::eval::Evaluator;
+use crate::history::History;
+use chess::{Board, BoardStatus, ChessMove, MoveGen};
+use serde::{Serialize, Deserialize};
+use indicatif::ProgressBar;
+use std::any::Any;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The value of being checkmated, for the side to move. It is below the evaluation of
+/// any position by any evaluator, so that a mate is always preferred to winning
+/// material.
+pub const CHECKMATED_VALUE: f64 = -1000.0;
+
+/// The parts of an engine used by a search: the evaluator and its cache, the history of
+/// positions leading to the node being searched, and the score of a draw.
+pub struct SearchContext<'a> {
+    pub evaluator: &'a mut dyn Evaluator,
+    pub history: &'a mut History,
+    pub cache: &'a mut EvalCache,
+    pub contempt: f64,
+}
+
+impl<'a> SearchContext<'a> {
+    /// Return the evaluation of a terminal node, from the evaluation cache if possible.
+    pub fn evaluate_terminal(&mut self, board: &Board) -> f64 {
+        let halfmove_clock = self.history.halfmove_clock();
+        let key = EvalCache::key(board, self.evaluator.uses_halfmove_clock().then_some(halfmove_clock));
+        if let Some(value) = self.cache.get(key) {
+            return value;
+        }
+
+        let value = self.evaluator.evaluate(board, halfmove_clock);
+        self.cache.insert(key, value);
+        value
+    }
+
+    /// Return the value of a drawn node, from the perspective of the side to move. A
+    /// positive contempt makes the engine avoid draws.
+    pub fn draw_value(&self, board: &Board) -> f64 {
+        if board.side_to_move() == self.history.root_color() {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+
+    /// Return the value of `board` from the perspective of the side to move if the
+    /// search should stop there, because the game is over, it is drawn, or there is no
+    /// depth left, and `None` otherwise. Checkmate is checked first, since a mate on
+    /// the hundredth ply wins rather than drawing by the fifty-move rule.
+    pub fn leaf_value(&mut self, board: &Board, depth: u8) -> Option<f64> {
+        match board.status() {
+            BoardStatus::Checkmate => return Some(CHECKMATED_VALUE),
+            BoardStatus::Stalemate => return Some(self.draw_value(board)),
+            BoardStatus::Ongoing => {}
+        }
+        if self.history.is_draw() {
+            return Some(self.draw_value(board));
+        }
+        if depth == 0 {
+            return Some(self.evaluate_terminal(board));
+        }
+
+        None
+    }
+
+    /// Return the board after making move `m` on `board`, recording the new position in
+    /// the history. The caller must call `unmake_move` once the new position has been
+    /// searched.
+    pub fn make_move(&mut self, board: &Board, m: ChessMove) -> Board {
+        let new_board = board.make_move_new(m);
+        self.history.push(board, m, &new_board);
+        self.evaluator.make_move(board, &new_board);
+
+        new_board
+    }
+
+    /// Undo the last call to `make_move`.
+    pub fn unmake_move(&mut self) {
+        self.history.pop();
+        self.evaluator.unmake_move();
+    }
+}
+
+/// A search algorithm, used by an engine to evaluate non-terminal nodes. Implementations
+/// are saved with the engine, tagged with their type name.
+#[typetag::serde(tag = "type")]
+pub trait Search: Any {
+    /// Return the evaluation of `board` searched to `depth` ply, from the perspective
+    /// of the side to move.
+    fn evaluate(&mut self, ctx: &mut SearchContext, board: &Board, depth: u8) -> f64;
+
+    /// Print any statistics collected by the search.
+    fn print_stats(&self) {}
+}
+
+/// Search by the negamax algorithm, without pruning.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Unpruned;
+
+#[typetag::serde]
+impl Search for Unpruned {
+    fn evaluate(&mut self, ctx: &mut SearchContext, board: &Board, depth: u8) -> f64 {
+        if let Some(value) = ctx.leaf_value(board, depth) {
+            return value;
+        }
+
+        let mut value = f64::NEG_INFINITY;
+        for m in MoveGen::new_legal(board) {
+            let temp_board = ctx.make_move(board, m);
+            value = value.max(-self.evaluate(ctx, &temp_board, depth - 1));
+            ctx.unmake_move();
+        }
+
+        value
+    }
+}
+
+/// Return the negamax value of a node from the values of its children, with alpha-beta
+/// pruning, where `child` is the search used to evaluate each child from the
+/// perspective of its own side to move.
+fn evaluate_children_ab(
+    ctx: &mut SearchContext,
+    board: &Board,
+    depth: u8,
+    alpha: &mut f64,
+    beta: &mut f64,
+    child: &mut dyn FnMut(&mut SearchContext, &Board, u8, &mut f64, &mut f64) -> f64,
+) -> f64 {
+    let mut value = f64::NEG_INFINITY;
+    for m in MoveGen::new_legal(board) {
+        let temp_board = ctx.make_move(board, m);
+        let mut child_alpha = -*beta;
+        let mut child_beta = -*alpha;
+        value = value.max(-child(ctx, &temp_board, depth - 1, &mut child_alpha, &mut child_beta));
+        ctx.unmake_move();
+        if value >= *beta {
+            break;
+        }
+        *alpha = alpha.max(value);
+    }
+
+    value
+}
+
+/// Search by the negamax algorithm, with alpha-beta pruning.
+#[derive(Serialize, Deserialize, Default)]
+pub struct AlphaBeta;
+
+impl AlphaBeta {
+    /// Return the evaluation of `board` searched to `depth` ply within the window
+    /// from `alpha` to `beta`.
+    pub fn evaluate_window(ctx: &mut SearchContext, board: &Board, depth: u8, alpha: &mut f64, beta: &mut f64) -> f64 {
+        if let Some(value) = ctx.leaf_value(board, depth) {
+            return value;
+        }
+
+        evaluate_children_ab(ctx, board, depth, alpha, beta, &mut AlphaBeta::evaluate_window)
+    }
+}
+
+#[typetag::serde]
+impl Search for AlphaBeta {
+    fn evaluate(&mut self, ctx: &mut SearchContext, board: &Board, depth: u8) -> f64 {
+        let mut alpha = f64::NEG_INFINITY;
+        let mut beta = f64::INFINITY;
+
+        AlphaBeta::evaluate_window(ctx, board, depth, &mut alpha, &mut beta)
+    }
+}
+
+/// A single Multi-ProbCut cut pair. At nodes searched to `depth`, a search to
+/// `shallow_depth` is used to predict the result of the deep search, as
+/// `deep = a * shallow + b` with residual standard deviation `sigma`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ProbCutPair {
+    pub depth: u8,
+    pub shallow_depth: u8,
+    pub a: f64,
+    pub b: f64,
+    pub sigma: f64,
+}
+
+impl ProbCutPair {
+    /// Return an uncalibrated cut pair for the given depths, using the values taken
+    /// from the paper linked in the plan.
+    pub fn new(depth: u8, shallow_depth: u8) -> ProbCutPair {
+        ProbCutPair {
+            depth,
+            shallow_depth,
+            a: 1.0,
+            b: 0.0,
+            sigma: 0.5,
+        }
+    }
+}
+
+impl FromStr for ProbCutPair {
+    type Err = String;
+
+    /// Parse an uncalibrated cut pair written as `depth:shallow_depth`, such as `3:1`.
+    fn from_str(s: &str) -> Result<ProbCutPair, String> {
+        let (depth, shallow_depth) = s.split_once(':').ok_or(format!("expected depth:shallow_depth, got {}", s))?;
+        let depth: u8 = depth.trim().parse().map_err(|_| format!("invalid depth: {}", depth))?;
+        let shallow_depth: u8 = shallow_depth.trim().parse().map_err(|_| format!("invalid shallow depth: {}", shallow_depth))?;
+        if shallow_depth >= depth {
+            return Err(format!("the shallow depth of {} must be less than the depth", s));
+        }
+
+        Ok(ProbCutPair::new(depth, shallow_depth))
+    }
+}
+
+/// Counts of how often a cut pair was tried, how often it made a cut, and how many
+/// of those cuts disagreed with a full search (only counted while verifying).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProbCutStats {
+    pub tries: u64,
+    pub cuts: u64,
+    pub verified_cuts: u64,
+    pub wrong_cuts: u64,
+}
+
+/// Fit `y = a * x + b` by least squares, and return `a`, `b` and the standard deviation
+/// of the residuals.
+fn fit_linear(xs: &[f64], ys: &[f64]) -> (f64, f64, f64) {
+    let n = xs.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    for (x, y) in xs.iter().zip(ys) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x).powi(2);
+    }
+
+    // an `x` that never changes says nothing about `y`, so fall back to the identity
+    // rather than dividing by zero
+    let a = if variance_x > 0.0 { covariance / variance_x } else { 1.0 };
+    let b = mean_y - a * mean_x;
+
+    let residual_sum_squares: f64 = xs
+        .iter()
+        .zip(ys)
+        .map(|(x, y)| (y - (a * x + b)).powi(2))
+        .sum();
+    let stdev = (residual_sum_squares / (n - 2.0).max(1.0)).sqrt();
+
+    (a, b, stdev)
+}
+
+/// The width of the null windows of the shallow searches of ProbCut, small enough not
+/// to matter next to evaluations of about ±1.
+const NULL_WINDOW: f64 = 1e-6;
+
+/// Search by the negamax algorithm, with alpha-beta pruning and the Multi-ProbCut
+/// extension. A cut is made when the shallow result is at least `t` standard
+/// deviations outside the window.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProbCut {
+    pub t: f64,
+    pub pairs: Vec<ProbCutPair>,
+    #[serde(skip)]
+    pub verify: bool,
+    #[serde(skip)]
+    stats: Vec<ProbCutStats>,
+}
+
+impl ProbCut {
+    /// Return a single-pair configuration for searches of the given depth, which cuts
+    /// one ply below the top of each search using a shallow search of half the
+    /// remaining depth. The top of a search always has an infinite window, so a pair
+    /// there could never cut. Searches of less than two ply have no pairs.
+    pub fn for_depth(search_depth: u8) -> ProbCut {
+        let pairs = match search_depth.checked_sub(1) {
+            Some(depth) if depth > 0 => vec![ProbCutPair::new(depth, depth / 2)],
+            _ => Vec::new(),
+        };
+
+        ProbCut {
+            t: 1.5,
+            pairs,
+            verify: false,
+            stats: Vec::new(),
+        }
+    }
+
+    /// Return the evaluation of `board` searched to `depth` ply within the window
+    /// from `alpha` to `beta`.
+    pub fn evaluate_window(&mut self, ctx: &mut SearchContext, board: &Board, depth: u8, alpha: &mut f64, beta: &mut f64) -> f64 {
+        if let Some(value) = ctx.leaf_value(board, depth) {
+            return value;
+        }
+
+        let num_pairs = self.pairs.len();
+        self.stats.resize(num_pairs, ProbCutStats::default());
+
+        for i in 0..num_pairs {
+            let pair = self.pairs[i];
+            // a pair with a non-positive slope would invert the cut conditions
+            if pair.depth != depth || pair.shallow_depth >= depth || pair.a <= 0.0 {
+                continue;
+            }
+            self.stats[i].tries += 1;
+
+            let bound = (self.t * pair.sigma + *beta - pair.b) / pair.a;
+            let (mut window_alpha, mut window_beta) = (bound - NULL_WINDOW, bound);
+            if self.evaluate_window(ctx, board, pair.shallow_depth, &mut window_alpha, &mut window_beta) >= bound {
+                self.record_cut(ctx, i, board, depth, true, *beta);
+                return *beta;
+            }
+
+            let bound = (-self.t * pair.sigma + *alpha - pair.b) / pair.a;
+            let (mut window_alpha, mut window_beta) = (bound, bound + NULL_WINDOW);
+            if self.evaluate_window(ctx, board, pair.shallow_depth, &mut window_alpha, &mut window_beta) <= bound {
+                self.record_cut(ctx, i, board, depth, false, *alpha);
+                return *alpha;
+            }
+        }
+
+        evaluate_children_ab(ctx, board, depth, alpha, beta, &mut |ctx, board, depth, alpha, beta| {
+            self.evaluate_window(ctx, board, depth, alpha, beta)
+        })
+    }
+
+    /// Count a cut made by cut pair `i`, and if verification is on, check it against a
+    /// full alpha-beta search of the node. A cut is wrong if the full search value lies
+    /// on the other side of the returned bound.
+    fn record_cut(&mut self, ctx: &mut SearchContext, i: usize, board: &Board, depth: u8, fail_high: bool, bound: f64) {
+        self.stats[i].cuts += 1;
+
+        if self.verify {
+            let value = AlphaBeta.evaluate(ctx, board, depth);
+
+            self.stats[i].verified_cuts += 1;
+            if (fail_high && value < bound) || (!fail_high && value > bound) {
+                self.stats[i].wrong_cuts += 1;
+            }
+        }
+    }
+
+    /// Set the cut pairs, clearing the statistics.
+    pub fn set_pairs(&mut self, pairs: Vec<ProbCutPair>) {
+        self.pairs = pairs;
+        self.stats.clear();
+    }
+
+    /// Return the statistics of each cut pair, in the same order as the pairs.
+    pub fn stats(&self) -> Vec<(ProbCutPair, ProbCutStats)> {
+        self.pairs
+            .iter()
+            .enumerate()
+            .map(|(i, pair)| (*pair, self.stats.get(i).copied().unwrap_or_default()))
+            .collect()
+    }
+
+    /// Fit each cut pair to the evaluator in `ctx`, by searching each of the given
+    /// positions to both the shallow and the deep depth of the pair and regressing the
+    /// deep results on the shallow ones.
+    pub fn calibrate(&mut self, ctx: &mut SearchContext, positions: &[Board]) {
+        assert!(positions.len() >= 2, "calibration needs at least two positions");
+
+        // search each position once per distinct depth used by the cut pairs
+        let mut depths: Vec<u8> = Vec::new();
+        for pair in &self.pairs {
+            depths.push(pair.depth);
+            depths.push(pair.shallow_depth);
+        }
+        depths.sort_unstable();
+        depths.dedup();
+
+        let mut values: HashMap<u8, Vec<f64>> = HashMap::new();
+        let pb = ProgressBar::new((positions.len() * depths.len()) as u64);
+        for &depth in &depths {
+            let mut depth_values = Vec::with_capacity(positions.len());
+            for board in positions {
+                *ctx.history = History::new(board);
+                depth_values.push(AlphaBeta.evaluate(ctx, board, depth));
+                pb.inc(1);
+            }
+            values.insert(depth, depth_values);
+        }
+        pb.finish();
+
+        println!("Calibration finished.");
+        for pair in self.pairs.iter_mut() {
+            let (a, b, sigma) = fit_linear(&values[&pair.shallow_depth], &values[&pair.depth]);
+            if a <= 0.0 {
+                println!(
+                    "Depth {} <- {}: the fitted slope {} is not positive, keeping the previous parameters.",
+                    pair.depth, pair.shallow_depth, a
+                );
+                continue;
+            }
+            pair.a = a;
+            pair.b = b;
+            pair.sigma = sigma;
+
+            println!("Depth {} <- {}: A: {} B: {} Sigma: {}", pair.depth, pair.shallow_depth, a, b, sigma);
+        }
+        self.stats.clear();
+    }
+}
+
+#[typetag::serde]
+impl Search for ProbCut {
+    fn evaluate(&mut self, ctx: &mut SearchContext, board: &Board, depth: u8) -> f64 {
+        let mut alpha = f64::NEG_INFINITY;
+        let mut beta = f64::INFINITY;
+
+        self.evaluate_window(ctx, board, depth, &mut alpha, &mut beta)
+    }
+
+    fn print_stats(&self) {
+        for (pair, stats) in self.stats() {
+            print!(
+                "Depth {} <- {}: {} tries, {} cuts",
+                pair.depth, pair.shallow_depth, stats.tries, stats.cuts
+            );
+            if stats.verified_cuts > 0 {
+                print!(", {}/{} verified cuts wrong", stats.wrong_cuts, stats.verified_cuts);
+            }
+            println!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Material;
+    use crate::features::tests::mirrored_pairs;
+
+    fn search_value(search: &mut dyn Search, board: &Board, depth: u8) -> f64 {
+        let mut evaluator = Material;
+        let mut history = History::new(board);
+        let mut cache = EvalCache::new(0);
+        let mut ctx = SearchContext {
+            evaluator: &mut evaluator,
+            history: &mut history,
+            cache: &mut cache,
+            contempt: 0.0,
+        };
+
+        search.evaluate(&mut ctx, board, depth)
+    }
+
+    // ProbCut is left out, since its cuts depend on the windows of the nodes, and so on
+    // the order of the moves, which mirroring changes
+    #[test]
+    fn searches_are_colour_symmetric() {
+        for (board, mirrored) in mirrored_pairs() {
+            for search in [&mut Unpruned as &mut dyn Search, &mut AlphaBeta] {
+                assert_eq!(search_value(search, &board, 3), search_value(search, &mirrored, 3));
+            }
+        }
+    }
+
+    #[test]
+    fn default_probcut_pairs_cut() {
+        let mut probcut = ProbCut::for_depth(3);
+        for (board, _) in mirrored_pairs() {
+            search_value(&mut probcut, &board, 3);
+        }
+
+        let (_, stats) = probcut.stats()[0];
+        assert!(stats.cuts > 0, "{} tries and no cuts", stats.tries);
+    }
+
+    /// Return the value of the leaf `board`, reached by a move from a position with the
+    /// given halfmove clock.
+    fn leaf_value(board: &Board, halfmove_clock: u16) -> Option<f64> {
+        let mut evaluator = Material;
+        let mut history = History::with_halfmove_clock(board, halfmove_clock);
+        let mut cache = EvalCache::new(0);
+        let mut ctx = SearchContext {
+            evaluator: &mut evaluator,
+            history: &mut history,
+            cache: &mut cache,
+            contempt: 0.0,
+        };
+
+        ctx.leaf_value(board, 1)
+    }
+
+    #[test]
+    fn mate_on_the_hundredth_ply_is_not_a_draw() {
+        let board = Board::from_str("7k/6Q1/6K1/8/8/8/8/8 b - - 100 1").unwrap();
+        assert_eq!(leaf_value(&board, 100), Some(CHECKMATED_VALUE));
+    }
+
+    #[test]
+    fn mate_is_preferred_to_material() {
+        let board = Board::from_str("6k1/5ppp/8/3n4/r7/8/5PPP/3R2K1 w - - 0 1").unwrap();
+        let mate = board.make_move_new(ChessMove::from_str("d1d8").unwrap());
+        let capture = board.make_move_new(ChessMove::from_str("d1d5").unwrap());
+        for depth in 0..3 {
+            for search in [&mut Unpruned as &mut dyn Search, &mut AlphaBeta] {
+                assert!(-search_value(search, &mate, depth) > -search_value(search, &capture, depth), "depth {}", depth);
+            }
+        }
+    }
+
+    #[test]
+    fn stalemate_is_a_draw() {
+        let board = Board::from_str("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(leaf_value(&board, 0), Some(0.0));
+    }
+
+    #[test]
+    fn alpha_beta_matches_unpruned() {
+        for (board, _) in mirrored_pairs() {
+            assert_eq!(search_value(&mut AlphaBeta, &board, 2), search_value(&mut Unpruned, &board, 2));
+        }
+    }
+}
@@ -0,0 +1,428 @@
+#![allow(dead_code)]
+
+use crate::engine::fen_halfmove_clock;
+use chess::{Board, ChessMove};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+/// How the training target of each position of a self-play game is chosen.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TrainingTarget {
+    /// Every position is fitted to the result of the game.
+    #[default]
+    MonteCarlo,
+    /// TD(λ): each position is fitted to the λ-return of the static evaluations of the
+    /// positions after it, ending in the result of the game.
+    Td { lambda: f64 },
+    /// TD-Leaf(λ): as `Td`, but using the value of the search from each position,
+    /// which is the evaluation of the leaf of its principal variation.
+    TdLeaf { lambda: f64 },
+}
+
+impl FromStr for TrainingTarget {
+    type Err = String;
+
+    /// Parse `monte-carlo`, or `td:λ` or `td-leaf:λ` with λ between 0 and 1.
+    fn from_str(s: &str) -> Result<TrainingTarget, String> {
+        let (name, lambda) = match s.split_once(':') {
+            Some((name, lambda)) => {
+                let lambda: f64 = lambda.parse().map_err(|_| format!("invalid λ {}", lambda))?;
+                if !(0.0..=1.0).contains(&lambda) {
+                    return Err(format!("λ must be between 0 and 1, not {}", lambda));
+                }
+                (name, Some(lambda))
+            }
+            None => (s, None),
+        };
+        match (name, lambda) {
+            ("monte-carlo", None) => Ok(TrainingTarget::MonteCarlo),
+            ("td", Some(lambda)) => Ok(TrainingTarget::Td { lambda }),
+            ("td-leaf", Some(lambda)) => Ok(TrainingTarget::TdLeaf { lambda }),
+            _ => Err(format!("unknown training target {}, expected monte-carlo, td:λ or td-leaf:λ", s)),
+        }
+    }
+}
+
+/// Return the λ-return of each position of a game, given the value of every position
+/// but the last and the result of the game, all from the same point of view. The last
+/// position is worth the result, and each earlier position `(1 - λ)` times the value of
+/// the next position plus `λ` times its return, so λ = 1 gives the result everywhere
+/// and λ = 0 gives the value of the next position.
+pub fn lambda_returns(values: &[f64], result: f64, lambda: f64) -> Vec<f64> {
+    let num_positions = values.len() + 1;
+    let mut returns = vec![result; num_positions];
+    for t in (0..num_positions - 1).rev() {
+        let next_value = if t + 1 < values.len() { values[t + 1] } else { result };
+        returns[t] = (1.0 - lambda) * next_value + lambda * returns[t + 1];
+    }
+
+    returns
+}
+
+/// A position labelled with a training target, from White's point of view.
+#[derive(Clone, Debug)]
+pub struct LabelledPosition {
+    pub board: Board,
+    pub halfmove_clock: u16,
+    pub target: f64,
+}
+
+/// How the numeric labels of a file of labelled positions are read. Game results
+/// written as `1-0`, `0-1` or `1/2-1/2` are read the same way in every mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LabelMode {
+    /// Numbers are results if every number in the file is 1, 0.5 or 0, and scores in
+    /// centipawns otherwise.
+    #[default]
+    Auto,
+    /// Numbers are White's score from the game: 1 for a win, 0.5 for a draw and 0 for a
+    /// loss.
+    Results,
+    /// Numbers are scores in centipawns.
+    Centipawns,
+}
+
+impl FromStr for LabelMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LabelMode, String> {
+        match s {
+            "auto" => Ok(LabelMode::Auto),
+            "results" => Ok(LabelMode::Results),
+            "centipawns" => Ok(LabelMode::Centipawns),
+            _ => Err(format!("unknown label mode {}, expected auto, results or centipawns", s)),
+        }
+    }
+}
+
+/// Return true if a numeric label could be White's score from a game.
+fn is_numeric_result(label: &str) -> bool {
+    label.parse::<f64>().is_ok_and(|value| value == 0.0 || value == 0.5 || value == 1.0)
+}
+
+/// Parse a label, which is either a game result or a number read as given by `mode`,
+/// which must not be `Auto`, all from White's point of view, into a target between -1
+/// and 1. A result is mapped to twice White's score minus one, and a score in
+/// centipawns to twice its expected score minus one, using the logistic relation in
+/// which 400 centipawns make a win ten times as likely as a loss.
+fn parse_label(label: &str, mode: LabelMode) -> Option<f64> {
+    match label {
+        "1-0" => Some(1.0),
+        "0-1" => Some(-1.0),
+        "1/2-1/2" => Some(0.0),
+        _ if mode == LabelMode::Results => is_numeric_result(label).then(|| 2.0 * label.parse::<f64>().unwrap() - 1.0),
+        _ => {
+            let centipawns: f64 = label.parse().ok()?;
+            Some(2.0 / (1.0 + 10f64.powf(-centipawns / 400.0)) - 1.0)
+        }
+    }
+}
+
+/// Read a set of labelled positions from a file with one position per line, as a FEN
+/// string and a label (see `parse_label`) separated by a semicolon or a comma, such as
+/// `fen;score` or `fen;result`, reading numeric labels as given by `mode`. The halfmove
+/// clock is taken from the FEN string if it has one. Blank lines and lines starting
+/// with `#` are skipped.
+pub fn read_labelled_positions(filename: &str, mode: LabelMode) -> Vec<LabelledPosition> {
+    let contents = std::fs::read_to_string(filename).unwrap();
+    let lines: Vec<(&str, &str)> = contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (fen, label) = line.rsplit_once([';', ',']).unwrap_or_else(|| panic!("missing label: {}", line));
+            (fen.trim(), label.trim())
+        })
+        .collect();
+
+    let mode = match mode {
+        LabelMode::Auto => {
+            let mut numbers = lines.iter().filter(|(_, label)| label.parse::<f64>().is_ok()).peekable();
+            if numbers.peek().is_some() && numbers.all(|(_, label)| is_numeric_result(label)) {
+                println!("Reading the numeric labels of {} as results, since they are all 1, 0.5 or 0.", filename);
+                LabelMode::Results
+            } else {
+                LabelMode::Centipawns
+            }
+        }
+        mode => mode,
+    };
+
+    lines
+        .into_iter()
+        .map(|(fen, label)| LabelledPosition {
+            board: Board::from_str(fen).unwrap_or_else(|_| panic!("invalid FEN: {}", fen)),
+            halfmove_clock: fen_halfmove_clock(fen),
+            target: parse_label(label, mode).unwrap_or_else(|| panic!("invalid label: {}", label)),
+        })
+        .collect()
+}
+
+/// The settings of a training run with held-out validation positions.
+#[derive(Clone, Debug)]
+pub struct TrainingConfig {
+    /// The number of positions fitted at once.
+    pub batch_size: usize,
+    /// The proportion of a labelled dataset held out for validation, which is always at
+    /// least one position.
+    pub validation_fraction: f64,
+    /// Stop once the validation loss has not improved for this many epochs or rounds,
+    /// and restore the evaluator with the best validation loss.
+    pub patience: Option<u32>,
+    /// The number of self-play games in a round, after which losses are measured.
+    pub games_per_round: u32,
+    /// A file to write the learning curve to, as CSV.
+    pub curve_file: Option<String>,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            batch_size: 32,
+            validation_fraction: 0.1,
+            patience: Some(3),
+            games_per_round: 10,
+            curve_file: None,
+        }
+    }
+}
+
+/// The training and validation loss after each epoch or round of training.
+#[derive(Clone, Debug, Default)]
+pub struct LearningCurve {
+    pub points: Vec<(u32, f64, f64)>,
+}
+
+impl LearningCurve {
+    /// Record and print the losses after the given epoch or round.
+    pub fn push(&mut self, step: u32, train_loss: f64, validation_loss: f64) {
+        println!("{}: train loss {} validation loss {}", step, train_loss, validation_loss);
+        self.points.push((step, train_loss, validation_loss));
+    }
+
+    /// Return the step with the lowest validation loss, if any.
+    pub fn best_step(&self) -> Option<u32> {
+        self.points
+            .iter()
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(step, _, _)| *step)
+    }
+
+    /// Return true if the validation loss has not improved on its best for the last
+    /// `patience` steps.
+    pub fn should_stop(&self, patience: u32) -> bool {
+        match self.best_step() {
+            Some(best) => self.points.last().unwrap().0 >= best + patience,
+            None => false,
+        }
+    }
+
+    /// Save the curve to the given file as CSV, with a header line.
+    pub fn save_csv(&self, filename: &str) {
+        let mut contents = String::from("step,train_loss,validation_loss\n");
+        for (step, train_loss, validation_loss) in &self.points {
+            contents += &format!("{},{},{}\n", step, train_loss, validation_loss);
+        }
+        std::fs::write(filename, contents).unwrap();
+    }
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HANDLER: Once = Once::new();
+
+/// Start catching Ctrl-C, so that training can stop cleanly at the end of the current
+/// game instead of the process being killed.
+pub fn catch_interrupts() {
+    HANDLER.call_once(|| {
+        ctrlc::set_handler(|| {
+            println!("\nInterrupted, stopping after the current game...");
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        })
+        .unwrap();
+    });
+}
+
+/// Return true if Ctrl-C has been pressed since interrupts were caught.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Counts of the self-play training done so far, saved with the engine so that
+/// training can be resumed from a checkpoint.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct TrainingProgress {
+    pub games_played: u32,
+    pub white_wins: u32,
+    pub black_wins: u32,
+    pub draws: u32,
+    /// The time spent playing the games, saved in seconds under the name it had when it
+    /// was counted in whole seconds, so that older checkpoints still load.
+    #[serde(rename = "secs_trained", serialize_with = "serialize_secs", deserialize_with = "deserialize_secs")]
+    pub time_trained: Duration,
+}
+
+fn serialize_secs<S: Serializer>(time: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(time.as_secs_f64())
+}
+
+fn deserialize_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    f64::deserialize(deserializer).map(Duration::from_secs_f64)
+}
+
+impl TrainingProgress {
+    /// Count a game with the given winner that took the given time.
+    pub fn record_game(&mut self, winner: i8, time: Duration) {
+        self.games_played += 1;
+        match winner {
+            1 => self.white_wins += 1,
+            -1 => self.black_wins += 1,
+            _ => self.draws += 1,
+        }
+        self.time_trained += time;
+    }
+}
+
+/// Where and how often to save checkpoints during self-play training. A checkpoint is
+/// the whole engine, including the evaluator's optimiser state and the training
+/// progress, so it can be loaded with `Engine::new_from_file` to resume training.
+#[derive(Clone, Debug)]
+pub struct Checkpoints {
+    pub filename: String,
+    pub every_games: Option<u32>,
+    pub every_minutes: Option<u64>,
+    last_games: u32,
+    last_time: Instant,
+}
+
+impl Checkpoints {
+    /// Save checkpoints to the given file every `every_games` games and every
+    /// `every_minutes` minutes, whichever comes first.
+    pub fn new(filename: &str, every_games: Option<u32>, every_minutes: Option<u64>) -> Checkpoints {
+        Checkpoints {
+            filename: filename.to_string(),
+            every_games,
+            every_minutes,
+            last_games: 0,
+            last_time: Instant::now(),
+        }
+    }
+
+    /// Return true if a checkpoint is due after the given number of games.
+    pub fn due(&self, games_played: u32) -> bool {
+        let games_due = self.every_games.is_some_and(|every| games_played >= self.last_games + every);
+        let time_due = self
+            .every_minutes
+            .is_some_and(|every| self.last_time.elapsed().as_secs() >= every * 60);
+
+        games_due || time_due
+    }
+
+    /// Note that a checkpoint was saved after the given number of games.
+    pub fn saved(&mut self, games_played: u32) {
+        self.last_games = games_played;
+        self.last_time = Instant::now();
+    }
+}
+
+/// How self-play games explore moves other than the best, so that training sees more
+/// than one game. Each setting is off by default.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Exploration {
+    /// The number of plies at the start of each game played uniformly at random.
+    pub random_plies: u32,
+    /// The temperature of the softmax over root move values used to choose moves for
+    /// the first `temperature_plies` plies after the random ones, or zero to always
+    /// choose the best move.
+    pub temperature: f64,
+    pub temperature_plies: u32,
+    /// The probability of playing a uniformly random move instead of the chosen one.
+    pub epsilon: f64,
+    /// The seed of the random number generator, for reproducible games, or `None` to
+    /// seed it from the operating system.
+    pub seed: Option<u64>,
+}
+
+impl Exploration {
+    /// Return a new random number generator seeded as configured, for training that
+    /// has already played `games_played` games. The state of the generator is not
+    /// saved in checkpoints, so a resumed run draws a new seed from the configured seed
+    /// and the number of games, rather than replaying the exploration of its first
+    /// games.
+    pub fn rng(&self, games_played: u32) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed ^ (games_played as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// Choose the move to play at the given ply from the values of the legal moves, for
+    /// the side to move. With no exploration this is the first move of highest value.
+    pub fn choose(&self, rng: &mut StdRng, ply: u32, values: &[(ChessMove, f64)]) -> ChessMove {
+        if ply < self.random_plies || (self.epsilon > 0.0 && rng.gen_bool(self.epsilon)) {
+            return values.choose(rng).unwrap().0;
+        }
+
+        if self.temperature > 0.0 && ply < self.random_plies + self.temperature_plies {
+            // subtract the best value before exponentiating, to avoid overflow
+            let best = values.iter().map(|(_, value)| *value).fold(f64::NEG_INFINITY, f64::max);
+            let weights: Vec<f64> = values
+                .iter()
+                .map(|(_, value)| ((value - best) / self.temperature).exp())
+                .collect();
+            let index = WeightedIndex::new(&weights).unwrap().sample(rng);
+            return values[index].0;
+        }
+
+        let mut best = values[0];
+        for &(m, value) in values {
+            if value > best.1 {
+                best = (m, value);
+            }
+        }
+
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_returns(returns: Vec<f64>, expected: &[f64]) {
+        assert_eq!(returns.len(), expected.len());
+        for (value, expected_value) in returns.iter().zip(expected) {
+            assert!((value - expected_value).abs() < 1e-9, "{:?} != {:?}", returns, expected);
+        }
+    }
+
+    #[test]
+    fn resumed_exploration_draws_new_numbers() {
+        let exploration = Exploration { seed: Some(7), ..Default::default() };
+        let draw = |games_played| exploration.rng(games_played).gen::<u64>();
+        assert_eq!(draw(0), draw(0));
+        assert_ne!(draw(0), draw(100));
+    }
+
+    #[test]
+    fn lambda_one_returns_the_result() {
+        assert_returns(lambda_returns(&[0.2, -0.3, 0.5], 1.0, 1.0), &[1.0; 4]);
+    }
+
+    #[test]
+    fn lambda_zero_returns_the_next_value() {
+        assert_returns(lambda_returns(&[0.2, -0.3, 0.5], -1.0, 0.0), &[-0.3, 0.5, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn lambda_returns_mix_values_and_the_result() {
+        // r3 = 1, r2 = 1, r1 = 0.5 * 0.6 + 0.5 * r2 = 0.8, r0 = 0.5 * 0.4 + 0.5 * r1 = 0.6
+        assert_returns(lambda_returns(&[0.2, 0.4, 0.6], 1.0, 0.5), &[0.6, 0.8, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+
+use serde::{Serialize, Deserialize};
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// A mapping from evaluations to win, draw and loss probabilities, and from those to
+/// centipawns. The probability of a win is `sigmoid(scale * value - draw_margin)` and
+/// that of a loss `sigmoid(-scale * value - draw_margin)`, with the rest being a draw,
+/// so `draw_margin` must be positive. The parameters are fitted to game results by
+/// `fit`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct WdlModel {
+    pub scale: f64,
+    pub draw_margin: f64,
+}
+
+impl Default for WdlModel {
+    fn default() -> WdlModel {
+        WdlModel { scale: 4.0, draw_margin: 1.0 }
+    }
+}
+
+impl WdlModel {
+    /// Return the probabilities of a win, a draw and a loss for the side whose
+    /// evaluation is `value`.
+    pub fn wdl(&self, value: f64) -> (f64, f64, f64) {
+        let win = sigmoid(self.scale * value - self.draw_margin);
+        let loss = sigmoid(-self.scale * value - self.draw_margin);
+
+        (win, 1.0 - win - loss, loss)
+    }
+
+    /// Return the expected score, from 0 for a certain loss to 1 for a certain win.
+    pub fn expected_score(&self, value: f64) -> f64 {
+        let (win, draw, _) = self.wdl(value);
+        win + draw / 2.0
+    }
+
+    /// Return the evaluation in centipawns, using the usual logistic relation in which
+    /// an advantage of 400 centipawns makes a win ten times as likely as a loss.
+    pub fn centipawns(&self, value: f64) -> i32 {
+        let score = self.expected_score(value).clamp(1e-6, 1.0 - 1e-6);
+        (400.0 * (score / (1.0 - score)).log10()).round() as i32
+    }
+
+    /// Return a description of the evaluation for people, in centipawns and win, draw
+    /// and loss percentages for the side whose evaluation is `value`.
+    pub fn describe(&self, value: f64) -> String {
+        let (win, draw, loss) = self.wdl(value);
+        format!(
+            "{:+} cp (W {:.0}% D {:.0}% L {:.0}%)",
+            self.centipawns(value),
+            100.0 * win,
+            100.0 * draw,
+            100.0 * loss
+        )
+    }
+
+    /// Fit the parameters by maximum likelihood to a set of evaluations and the results
+    /// of the games they were taken from, +1 for a win, 0 for a draw and -1 for a loss
+    /// by the side whose evaluation it is. Return the mean log-likelihood.
+    pub fn fit(&mut self, samples: &[(f64, f64)]) -> f64 {
+        let n = samples.len() as f64;
+        let learning_rate = 0.5;
+
+        for _ in 0..2000 {
+            let mut scale_gradient = 0.0;
+            let mut margin_gradient = 0.0;
+            for &(value, result) in samples {
+                let win = sigmoid(self.scale * value - self.draw_margin);
+                let loss = sigmoid(-self.scale * value - self.draw_margin);
+                if result > 0.0 {
+                    scale_gradient += (1.0 - win) * value;
+                    margin_gradient -= 1.0 - win;
+                } else if result < 0.0 {
+                    scale_gradient -= (1.0 - loss) * value;
+                    margin_gradient -= 1.0 - loss;
+                } else {
+                    let draw = 1.0 - win - loss;
+                    scale_gradient += (loss * (1.0 - loss) - win * (1.0 - win)) * value / draw;
+                    margin_gradient += (win * (1.0 - win) + loss * (1.0 - loss)) / draw;
+                }
+            }
+
+            self.scale += learning_rate * scale_gradient / n;
+            self.draw_margin = (self.draw_margin + learning_rate * margin_gradient / n).max(1e-3);
+        }
+
+        self.log_likelihood(samples)
+    }
+
+    /// Return the mean log-likelihood of the results of a set of samples, as for `fit`.
+    pub fn log_likelihood(&self, samples: &[(f64, f64)]) -> f64 {
+        let total: f64 = samples
+            .iter()
+            .map(|&(value, result)| {
+                let (win, draw, loss) = self.wdl(value);
+                let p = if result > 0.0 {
+                    win
+                } else if result < 0.0 {
+                    loss
+                } else {
+                    draw
+                };
+                p.max(1e-12).ln()
+            })
+            .sum();
+
+        total / samples.len() as f64
+    }
+}